@@ -0,0 +1,213 @@
+//! Textual encoding of ICRC-1 accounts.
+//!
+//! See `standards/ICRC-1/TextualEncoding.md` for the specification and
+//! `ref/Account.mo` for the reference implementation.
+
+use crate::{Account, Subaccount};
+use candid::Principal;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+const CHECKSUM_LENGTH: usize = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AccountParseError {
+    #[error("malformed account: {0}")]
+    Malformed(String),
+    #[error("the account representation is not canonical")]
+    NotCanonical,
+    #[error("the account checksum does not match")]
+    BadChecksum,
+}
+
+fn is_default(subaccount: &Subaccount) -> bool {
+    subaccount.iter().all(|b| *b == 0)
+}
+
+fn checksum(owner: &Principal, subaccount: &Subaccount) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(owner.as_slice());
+    hasher.update(subaccount);
+    data_encoding::BASE32_NOPAD
+        .encode(&hasher.finalize().to_be_bytes())
+        .to_lowercase()
+}
+
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.subaccount {
+            Some(subaccount) if !is_default(subaccount) => write!(
+                f,
+                "{}-{}.{}",
+                self.owner,
+                checksum(&self.owner, subaccount),
+                hex::encode(subaccount).trim_start_matches('0')
+            ),
+            _ => write!(f, "{}", self.owner),
+        }
+    }
+}
+
+impl FromStr for Account {
+    type Err = AccountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = |e: &dyn fmt::Display| AccountParseError::Malformed(e.to_string());
+        let parse_owner = |text: &str| {
+            let owner = Principal::from_text(text).map_err(|e| malformed(&e))?;
+            // Principal::from_text accepts upper-case letters.
+            if owner.to_text() != text {
+                return Err(AccountParseError::NotCanonical);
+            }
+            Ok(owner)
+        };
+
+        let (rest, subaccount_hex) = match s.split_once('.') {
+            None => {
+                return parse_owner(s).map(Account::from);
+            }
+            Some(parts) => parts,
+        };
+        let (owner_text, checksum_text) = rest
+            .rsplit_once('-')
+            .ok_or_else(|| malformed(&"expected a dash ('-') before the checksum"))?;
+
+        if checksum_text.len() != CHECKSUM_LENGTH {
+            return Err(AccountParseError::BadChecksum);
+        }
+        if subaccount_hex.len() > 64 {
+            return Err(malformed(
+                &"the subaccount is too long (expected at most 64 characters)",
+            ));
+        }
+        if subaccount_hex.is_empty() || subaccount_hex.starts_with('0') {
+            return Err(AccountParseError::NotCanonical);
+        }
+        if let Some(c) = subaccount_hex
+            .chars()
+            .find(|c| !matches!(c, '0'..='9' | 'a'..='f'))
+        {
+            return Err(malformed(&format!("invalid hex char: '{}'", c)));
+        }
+
+        let owner = parse_owner(owner_text)?;
+        let mut subaccount: Subaccount = [0; 32];
+        hex::decode_to_slice(format!("{:0>64}", subaccount_hex), &mut subaccount)
+            .map_err(|e| malformed(&e))?;
+
+        if checksum(&owner, &subaccount) != checksum_text {
+            return Err(AccountParseError::BadChecksum);
+        }
+        Ok(Account {
+            owner,
+            subaccount: Some(subaccount),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: &str = "k2t6j-2nvnp-4zjm3-25dtz-6xhaa-c7boj-5gayf-oj3xs-i43lp-teztq-6ae";
+
+    fn owner() -> Principal {
+        Principal::from_text(OWNER).unwrap()
+    }
+
+    fn account(subaccount: Option<Subaccount>) -> Account {
+        Account {
+            owner: owner(),
+            subaccount,
+        }
+    }
+
+    fn sequential_subaccount() -> Subaccount {
+        let mut subaccount = [0; 32];
+        for (i, b) in subaccount.iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+        subaccount
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(account(None).to_string(), OWNER);
+        assert_eq!(account(Some([0; 32])).to_string(), OWNER);
+        assert_eq!(
+            account(Some(sequential_subaccount())).to_string(),
+            format!(
+                "{}-dfxgiyy.102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20",
+                OWNER
+            )
+        );
+    }
+
+    #[test]
+    fn test_spec_examples() {
+        let mut one = [0; 32];
+        one[31] = 1;
+
+        let examples: Vec<(String, Result<Account, AccountParseError>)> = vec![
+            (OWNER.to_string(), Ok(account(None))),
+            (
+                format!("{}-q6bn32y.", OWNER),
+                Err(AccountParseError::NotCanonical),
+            ),
+            (
+                "k2t6j2nvnp4zjm3-25dtz6xhaac7boj5gayfoj3xs-i43lp-teztq-6ae".to_string(),
+                Err(AccountParseError::Malformed(String::new())),
+            ),
+            (format!("{}-6cc627i.1", OWNER), Ok(account(Some(one)))),
+            (
+                format!("{}-6cc627i.01", OWNER),
+                Err(AccountParseError::NotCanonical),
+            ),
+            (format!("{}.1", OWNER), Err(AccountParseError::BadChecksum)),
+            (
+                format!(
+                    "{}-dfxgiyy.102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20",
+                    OWNER
+                ),
+                Ok(account(Some(sequential_subaccount()))),
+            ),
+        ];
+
+        for (text, expected) in examples {
+            match (Account::from_str(&text), expected) {
+                (Ok(actual), Ok(expected)) => {
+                    assert_eq!(actual, expected, "{}", text);
+                    assert_eq!(actual.to_string(), text);
+                }
+                (Err(AccountParseError::Malformed(_)), Err(AccountParseError::Malformed(_))) => {}
+                (actual, expected) => assert_eq!(actual, expected, "{}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_non_canonical_forms() {
+        let canonical = account(Some(sequential_subaccount())).to_string();
+        for text in [
+            OWNER.to_uppercase(),
+            canonical.to_uppercase(),
+            canonical.replace(OWNER, &OWNER.to_uppercase()),
+            canonical.replace("dfxgiyy", "DFXGIYY"),
+            canonical.replace(".1020", ".1O20"),
+            canonical.replace("dfxgiyy", "dfxgiyz"),
+            format!("{}0", canonical),
+            format!("{}-aaaaaaa.{}", OWNER, "0".repeat(64)),
+        ] {
+            assert!(Account::from_str(&text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for subaccount in [[0xff; 32], [0x10; 32], sequential_subaccount()] {
+            let account = account(Some(subaccount));
+            assert_eq!(Account::from_str(&account.to_string()), Ok(account));
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Token amounts in human-readable units.
+
+use candid::Nat;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AmountParseError {
+    #[error("the amount is empty")]
+    Empty,
+    #[error("invalid amount {0:?}, expected a decimal number optionally followed by a symbol")]
+    Malformed(String),
+    #[error("the amount {amount} has more than {decimals} decimal places")]
+    TooManyDecimals { amount: String, decimals: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AmountError {
+    #[error("cannot combine amounts with {lhs} and {rhs} decimals")]
+    DecimalsMismatch { lhs: u8, rhs: u8 },
+    #[error("cannot combine amounts of {lhs:?} and {rhs:?}")]
+    SymbolMismatch {
+        lhs: Option<String>,
+        rhs: Option<String>,
+    },
+    #[error("the result of the operation is negative")]
+    Underflow,
+}
+
+/// An amount of tokens that knows the number of decimals of the token.
+///
+/// The amount is stored in base units, so `TokenAmount::from_base_units(125_000_000u64, 8)`
+/// is displayed as `1.25`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TokenAmount {
+    base_units: Nat,
+    decimals: u8,
+    symbol: Option<String>,
+}
+
+impl TokenAmount {
+    pub fn from_base_units(base_units: impl Into<Nat>, decimals: u8) -> Self {
+        Self {
+            base_units: base_units.into(),
+            decimals,
+            symbol: None,
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = Some(symbol.into());
+        self
+    }
+
+    /// Parses amounts like `"1.25"` or `"1.25 XTK"` of a token with the
+    /// specified number of decimals.
+    pub fn parse(s: &str, decimals: u8) -> Result<Self, AmountParseError> {
+        if s.is_empty() {
+            return Err(AmountParseError::Empty);
+        }
+        let malformed = || AmountParseError::Malformed(s.to_string());
+
+        let (number, symbol) = match s.split_once(' ') {
+            Some((number, symbol)) => {
+                if symbol.is_empty() || symbol.chars().any(char::is_whitespace) {
+                    return Err(malformed());
+                }
+                (number, Some(symbol.to_string()))
+            }
+            None => (s, None),
+        };
+        let (integer, fraction) = match number.split_once('.') {
+            Some((integer, fraction)) if !fraction.is_empty() => (integer, fraction),
+            Some(_) => return Err(malformed()),
+            None => (number, ""),
+        };
+        if integer.is_empty()
+            || !integer.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(malformed());
+        }
+        if fraction.len() > decimals as usize {
+            return Err(AmountParseError::TooManyDecimals {
+                amount: number.to_string(),
+                decimals,
+            });
+        }
+
+        let digits = format!(
+            "{}{:0<width$}",
+            integer,
+            fraction,
+            width = decimals as usize
+        );
+        let base_units = Nat::from_str(&digits).map_err(|_| malformed())?;
+        Ok(Self {
+            base_units,
+            decimals,
+            symbol,
+        })
+    }
+
+    pub fn base_units(&self) -> &Nat {
+        &self.base_units
+    }
+
+    pub fn into_base_units(self) -> Nat {
+        self.base_units
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// Returns an amount of the same token with a different number of base units.
+    pub fn with_base_units(&self, base_units: impl Into<Nat>) -> Self {
+        Self {
+            base_units: base_units.into(),
+            decimals: self.decimals,
+            symbol: self.symbol.clone(),
+        }
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self, AmountError> {
+        self.check_compatible(other)?;
+        Ok(self.with_base_units(self.base_units.clone() + other.base_units.clone()))
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self, AmountError> {
+        self.check_compatible(other)?;
+        if self.base_units < other.base_units {
+            return Err(AmountError::Underflow);
+        }
+        Ok(self.with_base_units(self.base_units.clone() - other.base_units.clone()))
+    }
+
+    pub fn multiply(&self, factor: u64) -> Self {
+        self.with_base_units(self.base_units.clone() * Nat::from(factor))
+    }
+
+    fn check_compatible(&self, other: &Self) -> Result<(), AmountError> {
+        if self.decimals != other.decimals {
+            return Err(AmountError::DecimalsMismatch {
+                lhs: self.decimals,
+                rhs: other.decimals,
+            });
+        }
+        match (&self.symbol, &other.symbol) {
+            (Some(lhs), Some(rhs)) if lhs != rhs => Err(AmountError::SymbolMismatch {
+                lhs: self.symbol.clone(),
+                rhs: other.symbol.clone(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.base_units.0.to_string();
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>width$}", digits, width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+
+        write!(f, "{}", integer)?;
+        if !fraction.is_empty() {
+            write!(f, ".{}", fraction)?;
+        }
+        if let Some(symbol) = &self.symbol {
+            write!(f, " {}", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<TokenAmount> for Nat {
+    fn from(amount: TokenAmount) -> Self {
+        amount.base_units
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let display =
+            |units: u64, decimals: u8| TokenAmount::from_base_units(units, decimals).to_string();
+        assert_eq!(display(125_000_000, 8), "1.25");
+        assert_eq!(display(100_000_000, 8), "1");
+        assert_eq!(display(1, 8), "0.00000001");
+        assert_eq!(display(0, 8), "0");
+        assert_eq!(display(12_345, 0), "12345");
+        assert_eq!(
+            TokenAmount::from_base_units(10_000u64, 8)
+                .with_symbol("XTK")
+                .to_string(),
+            "0.0001 XTK"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let amount = TokenAmount::parse("1.25 XTK", 8).unwrap();
+        assert_eq!(amount.base_units(), &Nat::from(125_000_000u64));
+        assert_eq!(amount.symbol(), Some("XTK"));
+        assert_eq!(amount.to_string(), "1.25 XTK");
+
+        assert_eq!(
+            TokenAmount::parse("0.00000001", 8).unwrap(),
+            TokenAmount::from_base_units(1u8, 8)
+        );
+        assert_eq!(
+            TokenAmount::parse("42", 0).unwrap(),
+            TokenAmount::from_base_units(42u8, 0)
+        );
+        assert_eq!(
+            TokenAmount::parse("123456789012345678901234567890", 18)
+                .unwrap()
+                .to_string(),
+            "123456789012345678901234567890"
+        );
+
+        assert_eq!(
+            TokenAmount::parse("1.123", 2),
+            Err(AmountParseError::TooManyDecimals {
+                amount: "1.123".to_string(),
+                decimals: 2
+            })
+        );
+        assert_eq!(TokenAmount::parse("", 8), Err(AmountParseError::Empty));
+        for malformed in [
+            "1.", ".5", "-1", "1,5", "1_000", "1.5 ", "1.5  XTK", "1.5 X K", "XTK", "1e8",
+        ] {
+            assert_eq!(
+                TokenAmount::parse(malformed, 8),
+                Err(AmountParseError::Malformed(malformed.to_string())),
+                "{}",
+                malformed
+            );
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let xtk = |s: &str| TokenAmount::parse(s, 8).unwrap();
+        assert_eq!(
+            xtk("1.25 XTK").checked_add(&xtk("0.75 XTK")),
+            Ok(xtk("2 XTK"))
+        );
+        assert_eq!(xtk("1.25 XTK").checked_sub(&xtk("0.25")), Ok(xtk("1 XTK")));
+        assert_eq!(
+            xtk("1 XTK").checked_sub(&xtk("1.5 XTK")),
+            Err(AmountError::Underflow)
+        );
+        assert_eq!(xtk("0.5 XTK").multiply(3), xtk("1.5 XTK"));
+        assert_eq!(
+            xtk("1 XTK").checked_add(&xtk("1 ABC")),
+            Err(AmountError::SymbolMismatch {
+                lhs: Some("XTK".to_string()),
+                rhs: Some("ABC".to_string())
+            })
+        );
+        assert_eq!(
+            xtk("1").checked_add(&TokenAmount::from_base_units(1u8, 6)),
+            Err(AmountError::DecimalsMismatch { lhs: 8, rhs: 6 })
+        );
+    }
+}
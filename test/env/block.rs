@@ -0,0 +1,418 @@
+//! Typed ICRC-1 and ICRC-2 blocks decoded from ICRC-3 values.
+//!
+//! See the "ICRC-1 and ICRC-2 Block Schema" section of the ICRC-3 standard.
+
+use crate::hash::Hash;
+use crate::{Account, Subaccount, Value};
+use candid::{Nat, Principal};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BlockDecodeError {
+    #[error("the block is not a map")]
+    NotAMap,
+    #[error("the block does not contain the required field {0}")]
+    MissingField(String),
+    #[error("the field {field} is not of type {expected}")]
+    WrongType {
+        field: String,
+        expected: &'static str,
+    },
+    #[error("the field {field} is not a valid account: {reason}")]
+    InvalidAccount { field: String, reason: String },
+}
+
+/// Fields shared by all ICRC-1 and ICRC-2 blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMeta {
+    /// The hash of the parent block, absent for the first block.
+    pub phash: Option<Hash>,
+    /// The time when the ledger added the block.
+    pub timestamp: u64,
+    /// The fee specified by the caller (`tx.fee`) or, if the caller did not
+    /// specify it, the fee charged by the ledger (`fee`).
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Mint {
+        meta: BlockMeta,
+        to: Account,
+        amount: Nat,
+    },
+    Burn {
+        meta: BlockMeta,
+        from: Account,
+        spender: Option<Account>,
+        amount: Nat,
+    },
+    Transfer {
+        meta: BlockMeta,
+        from: Account,
+        to: Account,
+        amount: Nat,
+    },
+    TransferFrom {
+        meta: BlockMeta,
+        from: Account,
+        to: Account,
+        spender: Option<Account>,
+        amount: Nat,
+    },
+    Approve {
+        meta: BlockMeta,
+        from: Account,
+        spender: Account,
+        amount: Nat,
+        expected_allowance: Option<Nat>,
+        expires_at: Option<u64>,
+    },
+    /// A block of a type that this crate does not know about.
+    Unknown(Value),
+}
+
+impl Block {
+    /// Decodes a block from its generic representation.
+    ///
+    /// The block type is defined by the `btype` field if it is set and by
+    /// the `tx.op` field otherwise. Blocks of other types decode into
+    /// [Block::Unknown].
+    pub fn from_value(value: &Value) -> Result<Self, BlockDecodeError> {
+        if value.as_map().is_none() {
+            return Err(BlockDecodeError::NotAMap);
+        }
+
+        let kind = match opt_text(value, "btype")? {
+            Some(btype) => match btype {
+                "1mint" => Kind::Mint,
+                "1burn" => Kind::Burn,
+                "1xfer" => Kind::Transfer,
+                "2xfer" => Kind::TransferFrom,
+                "2approve" => Kind::Approve,
+                _ => return Ok(Block::Unknown(value.clone())),
+            },
+            None => match opt_text(value, "tx.op")? {
+                Some("mint") => Kind::Mint,
+                Some("burn") => Kind::Burn,
+                Some("xfer") if value.get_path("tx.spender").is_some() => Kind::TransferFrom,
+                Some("xfer") => Kind::Transfer,
+                Some("approve") => Kind::Approve,
+                _ => return Ok(Block::Unknown(value.clone())),
+            },
+        };
+
+        let meta = BlockMeta {
+            phash: opt_blob(value, "phash")?
+                .map(|b| {
+                    Hash::try_from(b).map_err(|_| BlockDecodeError::WrongType {
+                        field: "phash".to_string(),
+                        expected: "32-byte Blob",
+                    })
+                })
+                .transpose()?,
+            timestamp: u64_field(value, "ts")?,
+            fee: match opt_nat(value, "tx.fee")? {
+                Some(fee) => Some(fee),
+                None => opt_nat(value, "fee")?,
+            },
+            memo: opt_blob(value, "tx.memo")?.map(|m| m.to_vec()),
+            created_at_time: opt_u64(value, "tx.ts")?,
+        };
+        let amount = required(value, "tx.amt", opt_nat)?;
+
+        Ok(match kind {
+            Kind::Mint => Block::Mint {
+                meta,
+                to: account(value, "tx.to")?,
+                amount,
+            },
+            Kind::Burn => Block::Burn {
+                meta,
+                from: account(value, "tx.from")?,
+                spender: opt_account(value, "tx.spender")?,
+                amount,
+            },
+            Kind::Transfer => Block::Transfer {
+                meta,
+                from: account(value, "tx.from")?,
+                to: account(value, "tx.to")?,
+                amount,
+            },
+            Kind::TransferFrom => Block::TransferFrom {
+                meta,
+                from: account(value, "tx.from")?,
+                to: account(value, "tx.to")?,
+                spender: opt_account(value, "tx.spender")?,
+                amount,
+            },
+            Kind::Approve => Block::Approve {
+                meta,
+                from: account(value, "tx.from")?,
+                spender: account(value, "tx.spender")?,
+                amount,
+                expected_allowance: opt_nat(value, "tx.expected_allowance")?,
+                expires_at: opt_u64(value, "tx.expires_at")?,
+            },
+        })
+    }
+
+    /// Returns the fields shared by all ICRC-1 and ICRC-2 blocks, or `None`
+    /// for blocks of unknown types.
+    pub fn meta(&self) -> Option<&BlockMeta> {
+        match self {
+            Block::Mint { meta, .. }
+            | Block::Burn { meta, .. }
+            | Block::Transfer { meta, .. }
+            | Block::TransferFrom { meta, .. }
+            | Block::Approve { meta, .. } => Some(meta),
+            Block::Unknown(_) => None,
+        }
+    }
+}
+
+impl TryFrom<&Value> for Block {
+    type Error = BlockDecodeError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        Block::from_value(value)
+    }
+}
+
+enum Kind {
+    Mint,
+    Burn,
+    Transfer,
+    TransferFrom,
+    Approve,
+}
+
+fn wrong_type(field: &str, expected: &'static str) -> BlockDecodeError {
+    BlockDecodeError::WrongType {
+        field: field.to_string(),
+        expected,
+    }
+}
+
+fn required<'a, T>(
+    value: &'a Value,
+    path: &str,
+    f: impl FnOnce(&'a Value, &str) -> Result<Option<T>, BlockDecodeError>,
+) -> Result<T, BlockDecodeError> {
+    f(value, path)?.ok_or_else(|| BlockDecodeError::MissingField(path.to_string()))
+}
+
+fn opt_text<'a>(value: &'a Value, path: &str) -> Result<Option<&'a str>, BlockDecodeError> {
+    value
+        .get_path(path)
+        .map(|v| v.as_text().ok_or_else(|| wrong_type(path, "Text")))
+        .transpose()
+}
+
+fn opt_blob<'a>(value: &'a Value, path: &str) -> Result<Option<&'a [u8]>, BlockDecodeError> {
+    value
+        .get_path(path)
+        .map(|v| v.as_blob().ok_or_else(|| wrong_type(path, "Blob")))
+        .transpose()
+}
+
+fn opt_nat(value: &Value, path: &str) -> Result<Option<Nat>, BlockDecodeError> {
+    value
+        .get_path(path)
+        .map(|v| v.as_nat().cloned().ok_or_else(|| wrong_type(path, "Nat")))
+        .transpose()
+}
+
+fn opt_u64(value: &Value, path: &str) -> Result<Option<u64>, BlockDecodeError> {
+    opt_nat(value, path)?
+        .map(|n| u64::try_from(&n.0).map_err(|_| wrong_type(path, "64-bit Nat")))
+        .transpose()
+}
+
+fn u64_field(value: &Value, path: &str) -> Result<u64, BlockDecodeError> {
+    required(value, path, opt_u64)
+}
+
+fn opt_account(value: &Value, path: &str) -> Result<Option<Account>, BlockDecodeError> {
+    let parts = match value.get_path(path) {
+        None => return Ok(None),
+        Some(v) => v.as_array().ok_or_else(|| wrong_type(path, "Array"))?,
+    };
+    let invalid = |reason: &str| BlockDecodeError::InvalidAccount {
+        field: path.to_string(),
+        reason: reason.to_string(),
+    };
+    let blobs = parts
+        .iter()
+        .map(|v| {
+            v.as_blob()
+                .ok_or_else(|| invalid("expected an array of blobs"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (owner, subaccount) = match blobs[..] {
+        [owner] => (owner, None),
+        [owner, subaccount] => (owner, Some(subaccount)),
+        _ => return Err(invalid("expected one or two elements")),
+    };
+    let owner = Principal::try_from_slice(owner).map_err(|e| invalid(&e.to_string()))?;
+    let subaccount = subaccount
+        .map(|s| Subaccount::try_from(s).map_err(|_| invalid("the subaccount must be 32 bytes")))
+        .transpose()?;
+    Ok(Some(Account { owner, subaccount }))
+}
+
+fn account(value: &Value, path: &str) -> Result<Account, BlockDecodeError> {
+    required(value, path, opt_account)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    fn nat(n: u64) -> Value {
+        Value::Nat(Nat::from(n))
+    }
+
+    fn account_value(owner: &[u8], subaccount: Option<[u8; 32]>) -> Value {
+        let mut parts = vec![Value::Blob(owner.to_vec())];
+        parts.extend(subaccount.map(|s| Value::Blob(s.to_vec())));
+        Value::Array(parts)
+    }
+
+    fn transfer_tx(op: Option<&str>) -> Value {
+        let mut tx = vec![
+            ("amt", nat(609_618)),
+            (
+                "from",
+                account_value(&[0, 0, 0, 0, 0, 0xf0, 0x13, 0x78, 1, 1], Some([0; 32])),
+            ),
+            ("to", account_value(&[1; 29], Some([0; 32]))),
+        ];
+        tx.extend(op.map(|op| ("op", Value::from(op))));
+        map(tx)
+    }
+
+    #[test]
+    fn test_btype_and_op_agree() {
+        let with_btype = map(vec![
+            ("btype", Value::from("1xfer")),
+            ("fee", nat(10)),
+            ("phash", Value::Blob(vec![7; 32])),
+            ("ts", nat(1_701_109_006_692_276_133)),
+            ("tx", transfer_tx(None)),
+        ]);
+        let with_op = map(vec![
+            ("fee", nat(10)),
+            ("phash", Value::Blob(vec![7; 32])),
+            ("ts", nat(1_701_109_006_692_276_133)),
+            ("tx", transfer_tx(Some("xfer"))),
+        ]);
+        let block = Block::from_value(&with_btype).unwrap();
+        assert_eq!(block, Block::from_value(&with_op).unwrap());
+        match block {
+            Block::Transfer {
+                meta, from, amount, ..
+            } => {
+                assert_eq!(meta.fee, Some(Nat::from(10u8)));
+                assert_eq!(meta.phash, Some([7; 32]));
+                assert_eq!(from.subaccount, Some([0; 32]));
+                assert_eq!(amount, Nat::from(609_618u64));
+            }
+            other => panic!("expected a transfer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_btype_takes_precedence_over_op() {
+        let block = map(vec![
+            ("btype", Value::from("1burn")),
+            ("ts", nat(1)),
+            ("tx", transfer_tx(Some("xfer"))),
+        ]);
+        assert!(matches!(
+            Block::from_value(&block).unwrap(),
+            Block::Burn { .. }
+        ));
+    }
+
+    #[test]
+    fn test_mint_without_subaccount() {
+        let block = map(vec![
+            ("ts", nat(1_675_241_149_669_614_928)),
+            (
+                "tx",
+                map(vec![
+                    ("op", Value::from("mint")),
+                    ("amt", nat(100_000)),
+                    ("to", account_value(&[5; 29], None)),
+                ]),
+            ),
+        ]);
+        match Block::from_value(&block).unwrap() {
+            Block::Mint { meta, to, .. } => {
+                assert_eq!(meta.phash, None);
+                assert_eq!(to, Account::from(Principal::from_slice(&[5; 29])));
+            }
+            other => panic!("expected a mint, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_block_types_are_preserved() {
+        let custom = map(vec![("btype", Value::from("42custom")), ("ts", nat(1))]);
+        assert_eq!(
+            Block::from_value(&custom).unwrap(),
+            Block::Unknown(custom.clone())
+        );
+        let no_type = map(vec![("ts", nat(1)), ("tx", map(vec![]))]);
+        assert_eq!(
+            Block::from_value(&no_type).unwrap(),
+            Block::Unknown(no_type.clone())
+        );
+    }
+
+    #[test]
+    fn test_malformed_blocks() {
+        assert_eq!(
+            Block::from_value(&nat(1)).unwrap_err(),
+            BlockDecodeError::NotAMap
+        );
+        let block = map(vec![
+            ("btype", Value::from("1mint")),
+            ("ts", nat(1)),
+            ("tx", map(vec![("amt", nat(1))])),
+        ]);
+        assert_eq!(
+            Block::from_value(&block).unwrap_err(),
+            BlockDecodeError::MissingField("tx.to".to_string())
+        );
+        let block = map(vec![
+            ("btype", Value::from("1mint")),
+            ("ts", nat(1)),
+            (
+                "tx",
+                map(vec![
+                    ("amt", nat(1)),
+                    ("to", account_value(&[5; 29], Some([1; 32]))),
+                    ("memo", nat(1)),
+                ]),
+            ),
+        ]);
+        assert!(matches!(
+            Block::from_value(&block).unwrap_err(),
+            BlockDecodeError::WrongType { .. }
+        ));
+    }
+}
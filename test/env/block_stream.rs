@@ -0,0 +1,453 @@
+use crate::icrc3::{get_archived_blocks, get_archives, get_blocks};
+use crate::{ArchiveInfo, ArchivedBlocks, BlockWithId, GetArchivesArgs, GetBlocksRequest};
+use crate::{LedgerCallError, LedgerEnv};
+use candid::{Nat, Principal};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::ops::Range;
+use thiserror::Error;
+
+const DEFAULT_BATCH_SIZE: u64 = 1_000;
+const DEFAULT_MAX_RETRIES: usize = 3;
+/// The maximum number of nested `archived_blocks` callbacks to follow.
+const MAX_CALLBACK_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum BlockStreamError {
+    #[error(transparent)]
+    Call(#[from] LedgerCallError),
+    #[error(
+        "neither the ledger nor its archives returned block {index} after {attempts} attempts"
+    )]
+    MissingBlock { index: u64, attempts: usize },
+    #[error("archive {canister_id} holds blocks {start} to {end}, which is not a valid range")]
+    InvalidArchiveRange {
+        canister_id: Principal,
+        start: Nat,
+        end: Nat,
+    },
+    #[error("block index {0} does not fit into u64")]
+    IndexOverflow(Nat),
+}
+
+fn to_u64(n: &Nat) -> Result<u64, BlockStreamError> {
+    u64::try_from(&n.0).map_err(|_| BlockStreamError::IndexOverflow(n.clone()))
+}
+
+/// Fetches the blocks of an ICRC-3 ledger in index order, regardless of
+/// whether the ledger or one of its archives holds them.
+///
+/// The stream follows the `archived_blocks` callbacks returned by
+/// `icrc3_get_blocks` and falls back to the archive list returned by
+/// `icrc3_get_archives` if neither the ledger nor the callbacks produce the
+/// next block. The `archive_env` function must return an environment that
+/// points to the archive canister with the specified id.
+pub struct BlockStream<'a, L, F> {
+    ledger: &'a L,
+    archive_env: F,
+    next: u64,
+    end: u64,
+    buffer: VecDeque<BlockWithId>,
+    archives: Option<Vec<ArchiveInfo>>,
+    batch_size: u64,
+    max_retries: usize,
+}
+
+/// Returns an `archive_env` function for [BlockStream::new] that calls the
+/// archives with the caller of `ledger`, see [LedgerEnv::with_canister].
+/// The function panics if `ledger` cannot call other canisters.
+pub fn sibling_env<L: LedgerEnv>(ledger: &L) -> impl Fn(Principal) -> L + '_ {
+    move |canister_id| {
+        ledger.with_canister(canister_id).unwrap_or_else(|| {
+            panic!(
+                "the ledger environment cannot call canister {}",
+                canister_id
+            )
+        })
+    }
+}
+
+impl<'a, L, F, A> BlockStream<'a, L, F>
+where
+    L: LedgerEnv,
+    F: Fn(Principal) -> A,
+    A: LedgerEnv,
+{
+    /// Creates a stream of blocks with indices in the specified range.
+    /// The range is truncated to the length of the block log.
+    pub fn new(ledger: &'a L, range: Range<u64>, archive_env: F) -> Self {
+        Self {
+            ledger,
+            archive_env,
+            next: range.start,
+            end: range.end,
+            buffer: VecDeque::new(),
+            archives: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    /// Sets the maximum number of blocks requested in a single call.
+    pub fn batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets how many times the stream asks for a block that neither the
+    /// ledger nor its archives returned before giving up.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Returns the next block or `None` if the stream reached the end of the
+    /// range. Once the stream returns an error, it does not yield any blocks.
+    pub async fn next(&mut self) -> Option<Result<BlockWithId, BlockStreamError>> {
+        if self.buffer.is_empty() && self.next < self.end {
+            if let Err(err) = self.fill_buffer().await {
+                self.end = self.next;
+                return Some(Err(err));
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+
+    /// Fetches all the remaining blocks of the stream.
+    pub async fn try_collect(mut self) -> Result<Vec<BlockWithId>, BlockStreamError> {
+        let mut blocks = vec![];
+        while let Some(block) = self.next().await {
+            blocks.push(block?);
+        }
+        Ok(blocks)
+    }
+
+    async fn fill_buffer(&mut self) -> Result<(), BlockStreamError> {
+        let mut attempts = 0;
+        while self.buffer.is_empty() && self.next < self.end {
+            if attempts > self.max_retries {
+                return Err(BlockStreamError::MissingBlock {
+                    index: self.next,
+                    attempts,
+                });
+            }
+            attempts += 1;
+
+            let length = self.batch_size.min(self.end - self.next);
+            let result =
+                get_blocks(self.ledger, vec![GetBlocksRequest::new(self.next, length)]).await?;
+            self.end = self.end.min(to_u64(&result.log_length)?);
+
+            let mut blocks = result.blocks;
+            blocks.extend(self.follow_callbacks(result.archived_blocks).await?);
+            if self.accept(blocks)? {
+                continue;
+            }
+
+            // The ledger might have archived the blocks between our calls,
+            // or it might not return callbacks at all: consult the list of
+            // archives directly.
+            if let Some(archive) = self.find_archive(self.next).await? {
+                let end = self.end.min(to_u64(&archive.end)?.saturating_add(1));
+                let archive_env = (self.archive_env)(archive.canister_id);
+                let result = get_blocks(
+                    &archive_env,
+                    vec![GetBlocksRequest::new(self.next, end - self.next)],
+                )
+                .await?;
+                let mut blocks = result.blocks;
+                blocks.extend(self.follow_callbacks(result.archived_blocks).await?);
+                if self.accept(blocks)? {
+                    continue;
+                }
+            }
+            // The list of archives might be stale, refresh it on the next attempt.
+            self.archives = None;
+        }
+        Ok(())
+    }
+
+    async fn follow_callbacks(
+        &self,
+        archived_blocks: Vec<ArchivedBlocks>,
+    ) -> Result<Vec<BlockWithId>, BlockStreamError> {
+        let mut blocks = vec![];
+        let mut pending = archived_blocks;
+        for _ in 0..MAX_CALLBACK_DEPTH {
+            if pending.is_empty() {
+                break;
+            }
+            let mut nested = vec![];
+            for archived in pending.iter() {
+                let archive_env = (self.archive_env)(archived.canister_id());
+                let result = get_archived_blocks(&archive_env, archived).await?;
+                blocks.extend(result.blocks);
+                nested.extend(result.archived_blocks);
+            }
+            pending = nested;
+        }
+        Ok(blocks)
+    }
+
+    /// Moves the blocks that continue the stream into the buffer.
+    /// Returns false if none of the blocks did.
+    fn accept(&mut self, blocks: Vec<BlockWithId>) -> Result<bool, BlockStreamError> {
+        let mut indexed = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            indexed.push((to_u64(&block.id)?, block));
+        }
+        indexed.sort_by_key(|(id, _)| *id);
+
+        let start = self.next;
+        for (id, block) in indexed {
+            if id < self.next || id >= self.end {
+                continue;
+            }
+            if id > self.next {
+                // A gap: the missing blocks will be requested again.
+                break;
+            }
+            self.buffer.push_back(block);
+            self.next += 1;
+        }
+        Ok(self.next > start)
+    }
+
+    async fn find_archive(&mut self, index: u64) -> Result<Option<ArchiveInfo>, BlockStreamError> {
+        if self.archives.is_none() {
+            let mut archives: Vec<ArchiveInfo> = vec![];
+            loop {
+                let from = archives.last().map(|a| a.canister_id);
+                let page = get_archives(self.ledger, GetArchivesArgs { from }).await?;
+                let done = page.is_empty() || page.iter().any(|a| archives.iter().any(|b| b == a));
+                archives.extend(page);
+                if done {
+                    break;
+                }
+            }
+            self.archives = Some(archives);
+        }
+        for archive in self.archives.iter().flatten() {
+            if archive.end < archive.start {
+                return Err(BlockStreamError::InvalidArchiveRange {
+                    canister_id: archive.canister_id,
+                    start: archive.start.clone(),
+                    end: archive.end.clone(),
+                });
+            }
+            if to_u64(&archive.start)? <= index && index <= to_u64(&archive.end)? {
+                return Ok(Some(archive.clone()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GetBlocksArgs, GetBlocksFn, GetBlocksResult, Value};
+    use crate::{LedgerFuture, RejectCode};
+    use candid::utils::{decode_args, encode_args};
+
+    fn unexpected(method: &str) -> LedgerCallError {
+        LedgerCallError::Reject {
+            method: method.to_string(),
+            code: RejectCode::DestinationInvalid,
+            message: format!("unexpected method {}", method),
+        }
+    }
+
+    /// A ledger that keeps blocks `[0, first_local)` in an archive and
+    /// returns at most `page` blocks per call.
+    #[derive(Clone)]
+    struct FakeLedger {
+        canister_id: Principal,
+        archive_id: Principal,
+        log_length: u64,
+        first_local: u64,
+        page: u64,
+        callbacks: bool,
+    }
+
+    fn block(id: u64) -> BlockWithId {
+        BlockWithId {
+            id: Nat::from(id),
+            block: Value::Map(vec![("ts".to_string(), Value::Nat(Nat::from(id)))]),
+        }
+    }
+
+    impl FakeLedger {
+        fn held_range(&self) -> Range<u64> {
+            if self.canister_id == self.archive_id {
+                0..self.first_local
+            } else {
+                self.first_local..self.log_length
+            }
+        }
+
+        fn get_blocks(&self, args: GetBlocksArgs) -> GetBlocksResult {
+            let held = self.held_range();
+            let mut blocks = vec![];
+            let mut archived = vec![];
+            for req in args {
+                let start = to_u64(&req.start).unwrap();
+                let end = start + to_u64(&req.length).unwrap();
+                for id in start.max(held.start)..end.min(held.end) {
+                    if blocks.len() as u64 == self.page {
+                        break;
+                    }
+                    blocks.push(block(id));
+                }
+                if self.callbacks && held.start > 0 && start < held.start {
+                    archived.push(ArchivedBlocks {
+                        args: vec![GetBlocksRequest::new(start, end.min(held.start) - start)],
+                        callback: GetBlocksFn::new(self.archive_id, "get_blocks".to_string()),
+                    });
+                }
+            }
+            GetBlocksResult {
+                log_length: Nat::from(self.log_length),
+                blocks,
+                archived_blocks: archived,
+            }
+        }
+
+        fn get_archives(&self) -> Vec<ArchiveInfo> {
+            vec![ArchiveInfo {
+                canister_id: self.archive_id,
+                start: Nat::from(0u8),
+                end: Nat::from(self.first_local - 1),
+            }]
+        }
+    }
+
+    impl LedgerEnv for FakeLedger {
+        fn fork(&self) -> Self {
+            self.clone()
+        }
+
+        fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+            Some(Self {
+                canister_id,
+                ..self.clone()
+            })
+        }
+
+        fn principal(&self) -> Principal {
+            Principal::anonymous()
+        }
+
+        fn time(&self) -> std::time::SystemTime {
+            std::time::SystemTime::UNIX_EPOCH
+        }
+
+        fn query_raw<'a>(
+            &'a self,
+            method: &'a str,
+            in_bytes: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                Ok(match method {
+                    "icrc3_get_blocks" | "get_blocks" => {
+                        let (args,): (GetBlocksArgs,) = decode_args(&in_bytes).unwrap();
+                        encode_args((self.get_blocks(args),)).unwrap()
+                    }
+                    "icrc3_get_archives" => {
+                        let (args,): (GetArchivesArgs,) = decode_args(&in_bytes).unwrap();
+                        let archives = if args.from.is_some() {
+                            vec![]
+                        } else {
+                            self.get_archives()
+                        };
+                        encode_args((archives,)).unwrap()
+                    }
+                    _ => return Err(unexpected(method)),
+                })
+            })
+        }
+
+        fn update_raw<'a>(
+            &'a self,
+            method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move { Err(unexpected(method)) })
+        }
+    }
+
+    fn fake_ledger(callbacks: bool) -> FakeLedger {
+        FakeLedger {
+            canister_id: Principal::from_slice(&[1]),
+            archive_id: Principal::from_slice(&[2]),
+            log_length: 25,
+            first_local: 10,
+            page: 4,
+            callbacks,
+        }
+    }
+
+    fn ids(blocks: &[BlockWithId]) -> Vec<u64> {
+        blocks.iter().map(|b| to_u64(&b.id).unwrap()).collect()
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        futures::executor::block_on(f)
+    }
+
+    #[test]
+    fn test_follows_callbacks() {
+        let ledger = fake_ledger(true);
+        let archive = |id| FakeLedger {
+            canister_id: id,
+            ..ledger.clone()
+        };
+        let blocks =
+            block_on(BlockStream::new(&ledger, 0..u64::MAX, archive).try_collect()).unwrap();
+        assert_eq!(ids(&blocks), (0..25).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_follows_callbacks_with_sibling_env() {
+        let ledger = fake_ledger(true);
+        let blocks =
+            block_on(BlockStream::new(&ledger, 5..20, sibling_env(&ledger)).try_collect()).unwrap();
+        assert_eq!(ids(&blocks), (5..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_falls_back_to_archive_list() {
+        let ledger = fake_ledger(false);
+        let archive = |id| FakeLedger {
+            canister_id: id,
+            ..ledger.clone()
+        };
+        let blocks = block_on(
+            BlockStream::new(&ledger, 3..17, archive)
+                .batch_size(5)
+                .try_collect(),
+        )
+        .unwrap();
+        assert_eq!(ids(&blocks), (3..17).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_reports_missing_blocks() {
+        let ledger = fake_ledger(false);
+        // The archive environment points to a canister that has no blocks.
+        let archive = |_| FakeLedger {
+            canister_id: Principal::from_slice(&[3]),
+            first_local: 25,
+            ..ledger.clone()
+        };
+        let mut stream = BlockStream::new(&ledger, 0..25, archive);
+        assert_eq!(
+            block_on(stream.next()).unwrap(),
+            Err(BlockStreamError::MissingBlock {
+                index: 0,
+                attempts: DEFAULT_MAX_RETRIES + 1
+            })
+        );
+        assert!(block_on(stream.next()).is_none());
+    }
+}
@@ -0,0 +1,360 @@
+//! Verification of the ICRC-3 tip certificate.
+//!
+//! See the "Blocks Verification" section of the ICRC-3 standard and
+//! https://internetcomputer.org/docs/current/references/ic-interface-spec#certification
+
+use crate::chain::Tip;
+use crate::hash::Hash;
+use crate::DataCertificate;
+use candid::Principal;
+use ic_certification::{Certificate, Delegation, HashTree, LookupResult};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+const IC_STATE_ROOT_DOMAIN_SEPARATOR: &[u8; 14] = b"\x0Dic-state-root";
+const BLS_KEY_DER_PREFIX: &[u8; 37] = b"\x30\x81\x82\x30\x1d\x06\x0d\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x01\x02\x01\x06\x0c\x2b\x06\x01\x04\x01\x82\xdc\x7c\x05\x03\x02\x01\x03\x61\x00";
+const BLS_KEY_LENGTH: usize = 96;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CertificateError {
+    #[error("failed to decode the certificate: {0}")]
+    MalformedCertificate(String),
+    #[error("failed to decode the hash tree: {0}")]
+    MalformedHashTree(String),
+    #[error("the public key is not a DER-encoded BLS key")]
+    MalformedPublicKey,
+    #[error("the certificate signature does not match the public key")]
+    InvalidSignature,
+    #[error("the delegation to subnet {subnet_id} does not cover canister {canister_id}")]
+    CanisterNotInDelegation {
+        subnet_id: Principal,
+        canister_id: Principal,
+    },
+    #[error("the certificate does not contain the path {0}")]
+    MissingPath(String),
+    #[error("the value at path {0} is malformed")]
+    MalformedValue(String),
+    #[error("the certified data of canister {0} does not match the hash tree")]
+    CertifiedDataMismatch(Principal),
+}
+
+/// Verifies the certificate returned by `icrc3_get_tip_certificate` and
+/// returns the certified tip of the chain.
+///
+/// The certificate must be signed by the `root_key` (in DER encoding, as
+/// returned by `Agent::read_root_key`, or raw), or by a subnet key delegated by it,
+/// and certify the hash tree as the certified data of `canister_id`.
+pub fn verify_tip_certificate(
+    certificate: &DataCertificate,
+    canister_id: Principal,
+    root_key: &[u8],
+) -> Result<Tip, CertificateError> {
+    let cert: Certificate = serde_cbor::from_slice(&certificate.certificate)
+        .map_err(|e| CertificateError::MalformedCertificate(e.to_string()))?;
+    verify_certificate(&cert, canister_id, root_key)?;
+
+    let hash_tree: HashTree = serde_cbor::from_slice(&certificate.hash_tree)
+        .map_err(|e| CertificateError::MalformedHashTree(e.to_string()))?;
+    let certified_data = lookup(
+        &cert.tree,
+        &[b"canister", canister_id.as_slice(), b"certified_data"],
+    )?;
+    if certified_data != hash_tree.digest() {
+        return Err(CertificateError::CertifiedDataMismatch(canister_id));
+    }
+
+    tip_from_hash_tree(&hash_tree)
+}
+
+/// Extracts the tip of the chain from the hash tree of a tip certificate
+/// without checking that the ledger certified it.
+pub fn tip_from_hash_tree(hash_tree: &HashTree) -> Result<Tip, CertificateError> {
+    let index = lookup(hash_tree, &[b"last_block_index"])?;
+    let hash = lookup(hash_tree, &[b"last_block_hash"])?;
+    Ok(Tip {
+        last_block_index: decode_leb128(index)
+            .ok_or_else(|| CertificateError::MalformedValue("last_block_index".to_string()))?,
+        last_block_hash: Hash::try_from(hash)
+            .map_err(|_| CertificateError::MalformedValue("last_block_hash".to_string()))?,
+    })
+}
+
+fn verify_certificate(
+    cert: &Certificate,
+    canister_id: Principal,
+    root_key: &[u8],
+) -> Result<(), CertificateError> {
+    let key = match &cert.delegation {
+        None => extract_der(root_key)?.to_vec(),
+        Some(delegation) => check_delegation(delegation, canister_id, root_key)?,
+    };
+    let mut msg = IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+    msg.extend_from_slice(&cert.tree.digest());
+    ic_verify_bls_signature::verify_bls_signature(&cert.signature, &msg, &key)
+        .map_err(|_| CertificateError::InvalidSignature)
+}
+
+/// Verifies the delegation certificate and returns the subnet key.
+fn check_delegation(
+    delegation: &Delegation,
+    canister_id: Principal,
+    root_key: &[u8],
+) -> Result<Vec<u8>, CertificateError> {
+    let cert: Certificate = serde_cbor::from_slice(&delegation.certificate)
+        .map_err(|e| CertificateError::MalformedCertificate(e.to_string()))?;
+    if cert.delegation.is_some() {
+        return Err(CertificateError::MalformedCertificate(
+            "nested delegations are not allowed".to_string(),
+        ));
+    }
+    verify_certificate(&cert, canister_id, root_key)?;
+
+    let subnet_id = Principal::try_from_slice(&delegation.subnet_id)
+        .map_err(|e| CertificateError::MalformedCertificate(e.to_string()))?;
+    let ranges_path: &[&[u8]] = &[b"subnet", subnet_id.as_slice(), b"canister_ranges"];
+    let ranges: Vec<(Principal, Principal)> =
+        serde_cbor::from_slice(lookup(&cert.tree, ranges_path)?)
+            .map_err(|_| CertificateError::MalformedValue(display_path(ranges_path)))?;
+    if !ranges
+        .iter()
+        .any(|(low, high)| low <= &canister_id && &canister_id <= high)
+    {
+        return Err(CertificateError::CanisterNotInDelegation {
+            subnet_id,
+            canister_id,
+        });
+    }
+
+    let key = lookup(
+        &cert.tree,
+        &[b"subnet", subnet_id.as_slice(), b"public_key"],
+    )?;
+    Ok(extract_der(key)?.to_vec())
+}
+
+/// Returns the raw BLS key from a DER-encoded key. Raw keys are accepted as is.
+fn extract_der(key: &[u8]) -> Result<&[u8], CertificateError> {
+    if key.len() == BLS_KEY_LENGTH {
+        return Ok(key);
+    }
+    if key.len() != BLS_KEY_DER_PREFIX.len() + BLS_KEY_LENGTH
+        || !key.starts_with(BLS_KEY_DER_PREFIX)
+    {
+        return Err(CertificateError::MalformedPublicKey);
+    }
+    Ok(&key[BLS_KEY_DER_PREFIX.len()..])
+}
+
+fn lookup<'a>(tree: &'a HashTree, path: &[&[u8]]) -> Result<&'a [u8], CertificateError> {
+    match tree.lookup_path(path) {
+        LookupResult::Found(value) => Ok(value),
+        _ => Err(CertificateError::MissingPath(display_path(path))),
+    }
+}
+
+fn display_path(path: &[&[u8]]) -> String {
+    path.iter()
+        .map(|label| match std::str::from_utf8(label) {
+            Ok(s) if s.chars().all(|c| c.is_ascii_graphic()) => s.to_string(),
+            _ => hex::encode(label),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn decode_leb128(bytes: &[u8]) -> Option<u64> {
+    let mut result: u64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        let shift = 7 * i as u32;
+        let bits = u64::from(byte & 0x7f);
+        if shift >= 64 || (bits << shift) >> shift != bits {
+            return None;
+        }
+        result |= bits << shift;
+        if byte & 0x80 == 0 {
+            return (i + 1 == bytes.len()).then_some(result);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bls12_381::hash_to_curve::{ExpandMsgXmd, HashToCurve};
+    use bls12_381::{G1Affine, G1Projective, G2Affine, Scalar};
+    use ic_certification::{fork, labeled, leaf};
+
+    const DST: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_NUL_";
+
+    fn public_key(secret: &Scalar) -> Vec<u8> {
+        let mut der = BLS_KEY_DER_PREFIX.to_vec();
+        der.extend_from_slice(&G2Affine::from(G2Affine::generator() * secret).to_compressed());
+        der
+    }
+
+    fn sign(secret: &Scalar, tree: HashTree, delegation: Option<Delegation>) -> Certificate {
+        let mut msg = IC_STATE_ROOT_DOMAIN_SEPARATOR.to_vec();
+        msg.extend_from_slice(&tree.digest());
+        let point =
+            <G1Projective as HashToCurve<ExpandMsgXmd<sha2_09::Sha256>>>::hash_to_curve(msg, DST);
+        Certificate {
+            tree,
+            signature: G1Affine::from(point * secret).to_compressed().to_vec(),
+            delegation,
+        }
+    }
+
+    fn tip_tree(index: &[u8], hash: &[u8]) -> HashTree {
+        fork(
+            labeled("last_block_hash", leaf(hash.to_vec())),
+            labeled("last_block_index", leaf(index.to_vec())),
+        )
+    }
+
+    fn certified_data_tree(canister_id: Principal, data: &HashTree) -> HashTree {
+        labeled(
+            "canister",
+            labeled(
+                canister_id.as_slice().to_vec(),
+                labeled("certified_data", leaf(data.digest().to_vec())),
+            ),
+        )
+    }
+
+    fn data_certificate(cert: &Certificate, hash_tree: &HashTree) -> DataCertificate {
+        DataCertificate {
+            certificate: serde_cbor::to_vec(cert).unwrap(),
+            hash_tree: serde_cbor::to_vec(hash_tree).unwrap(),
+        }
+    }
+
+    fn canister_id() -> Principal {
+        Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap()
+    }
+
+    #[test]
+    fn test_root_signed_certificate() {
+        let root = Scalar::from(42u64);
+        // 300 in LEB128.
+        let hash_tree = tip_tree(&[0xac, 0x02], &[7; 32]);
+        let cert = sign(&root, certified_data_tree(canister_id(), &hash_tree), None);
+        let tip = verify_tip_certificate(
+            &data_certificate(&cert, &hash_tree),
+            canister_id(),
+            &public_key(&root),
+        )
+        .unwrap();
+        assert_eq!(
+            tip,
+            Tip {
+                last_block_index: 300,
+                last_block_hash: [7; 32],
+            }
+        );
+
+        assert_eq!(
+            verify_tip_certificate(
+                &data_certificate(&cert, &hash_tree),
+                canister_id(),
+                &public_key(&Scalar::from(43u64)),
+            ),
+            Err(CertificateError::InvalidSignature)
+        );
+
+        let other = Principal::from_text("qjdve-lqaaa-aaaaa-aaaeq-cai").unwrap();
+        assert!(matches!(
+            verify_tip_certificate(
+                &data_certificate(&cert, &hash_tree),
+                other,
+                &public_key(&root),
+            ),
+            Err(CertificateError::MissingPath(_))
+        ));
+
+        let forged_tree = tip_tree(&[0xac, 0x02], &[8; 32]);
+        assert_eq!(
+            verify_tip_certificate(
+                &data_certificate(&cert, &forged_tree),
+                canister_id(),
+                &public_key(&root),
+            ),
+            Err(CertificateError::CertifiedDataMismatch(canister_id()))
+        );
+    }
+
+    #[test]
+    fn test_delegated_certificate() {
+        let root = Scalar::from(42u64);
+        let subnet = Scalar::from(7u64);
+        let subnet_id = Principal::from_slice(&[1, 2, 3]);
+
+        let delegation = |ranges: Vec<(Principal, Principal)>| {
+            let tree = labeled(
+                "subnet",
+                labeled(
+                    subnet_id.as_slice().to_vec(),
+                    fork(
+                        labeled(
+                            "canister_ranges",
+                            leaf(serde_cbor::to_vec(&ranges).unwrap()),
+                        ),
+                        labeled("public_key", leaf(public_key(&subnet))),
+                    ),
+                ),
+            );
+            Delegation {
+                subnet_id: subnet_id.as_slice().to_vec(),
+                certificate: serde_cbor::to_vec(&sign(&root, tree, None)).unwrap(),
+            }
+        };
+
+        let hash_tree = tip_tree(&[5], &[1; 32]);
+        let cert = sign(
+            &subnet,
+            certified_data_tree(canister_id(), &hash_tree),
+            Some(delegation(vec![(canister_id(), canister_id())])),
+        );
+        let tip = verify_tip_certificate(
+            &data_certificate(&cert, &hash_tree),
+            canister_id(),
+            &public_key(&root),
+        )
+        .unwrap();
+        assert_eq!(tip.last_block_index, 5);
+
+        let other = Principal::from_text("qjdve-lqaaa-aaaaa-aaaeq-cai").unwrap();
+        let cert = sign(
+            &subnet,
+            certified_data_tree(canister_id(), &hash_tree),
+            Some(delegation(vec![(other, other)])),
+        );
+        assert_eq!(
+            verify_tip_certificate(
+                &data_certificate(&cert, &hash_tree),
+                canister_id(),
+                &public_key(&root),
+            ),
+            Err(CertificateError::CanisterNotInDelegation {
+                subnet_id,
+                canister_id: canister_id(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_leb128() {
+        assert_eq!(decode_leb128(&[0]), Some(0));
+        assert_eq!(decode_leb128(&[0xe5, 0x8e, 0x26]), Some(624_485));
+        assert_eq!(
+            decode_leb128(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]),
+            Some(u64::MAX)
+        );
+        assert_eq!(
+            decode_leb128(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]),
+            None
+        );
+        assert_eq!(decode_leb128(&[0x80]), None);
+        assert_eq!(decode_leb128(&[0x01, 0x02]), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Verification of the ICRC-3 block log integrity.
+//!
+//! See the "Blocks Verification" section of the ICRC-3 standard.
+
+use crate::hash::{hash_value, Hash};
+use crate::{BlockWithId, Value};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+/// The last block of the chain as certified by the ledger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tip {
+    pub last_block_index: u64,
+    pub last_block_hash: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChainError {
+    #[error("expected block {expected}, got block {actual}")]
+    UnexpectedIndex { expected: u64, actual: String },
+    #[error("block 0 must not have a parent hash")]
+    UnexpectedParentHash,
+    #[error("block {index} does not have a parent hash")]
+    MissingParentHash { index: u64 },
+    #[error("the parent hash of block {index} is not a 32-byte blob")]
+    MalformedParentHash { index: u64 },
+    #[error(
+        "the parent hash of block {index} is {}, but the hash of block {} is {}",
+        hex::encode(actual), index - 1, hex::encode(expected)
+    )]
+    ParentHashMismatch {
+        index: u64,
+        expected: Hash,
+        actual: Hash,
+    },
+    #[error("the certified tip is block {certified}, but the chain ends at {actual:?}")]
+    TipIndexMismatch { certified: u64, actual: Option<u64> },
+    #[error(
+        "the certified hash of block {index} is {}, but the block hashes to {}",
+        hex::encode(certified),
+        hex::encode(actual)
+    )]
+    TipHashMismatch {
+        index: u64,
+        certified: Hash,
+        actual: Hash,
+    },
+}
+
+impl ChainError {
+    /// Returns the index of the first block that failed the verification,
+    /// if the error refers to a specific block.
+    pub fn index(&self) -> Option<u64> {
+        match self {
+            ChainError::UnexpectedIndex { expected, .. } => Some(*expected),
+            ChainError::UnexpectedParentHash => Some(0),
+            ChainError::MissingParentHash { index }
+            | ChainError::MalformedParentHash { index }
+            | ChainError::ParentHashMismatch { index, .. }
+            | ChainError::TipHashMismatch { index, .. } => Some(*index),
+            ChainError::TipIndexMismatch { .. } => None,
+        }
+    }
+}
+
+/// Verifies that a sequence of blocks forms a valid chain, block by block.
+///
+/// ```ignore
+/// let mut verifier = ChainVerifier::new();
+/// while let Some(block) = stream.next().await {
+///     verifier.push(&block?)?;
+/// }
+/// verifier.verify_tip(&tip)?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ChainVerifier {
+    last: Option<(u64, Hash)>,
+}
+
+impl ChainVerifier {
+    /// Creates a verifier for a chain starting at block 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a verifier for a chain starting right after the block with
+    /// the specified index and hash.
+    pub fn after(index: u64, hash: Hash) -> Self {
+        Self {
+            last: Some((index, hash)),
+        }
+    }
+
+    /// Checks that the block is the next block of the chain and returns its hash.
+    pub fn push(&mut self, block: &BlockWithId) -> Result<Hash, ChainError> {
+        let expected = self.last.map_or(0, |(index, _)| index + 1);
+        if block.id != expected {
+            return Err(ChainError::UnexpectedIndex {
+                expected,
+                actual: block.id.to_string(),
+            });
+        }
+        let phash = parent_hash(expected, &block.block)?;
+        match (self.last, phash) {
+            (None, None) => {}
+            (None, Some(_)) => return Err(ChainError::UnexpectedParentHash),
+            (Some(_), None) => return Err(ChainError::MissingParentHash { index: expected }),
+            (Some((_, parent)), Some(phash)) => {
+                if parent != phash {
+                    return Err(ChainError::ParentHashMismatch {
+                        index: expected,
+                        expected: parent,
+                        actual: phash,
+                    });
+                }
+            }
+        }
+        let hash = hash_value(&block.block);
+        self.last = Some((expected, hash));
+        Ok(hash)
+    }
+
+    /// Returns the index and the hash of the last verified block.
+    pub fn last(&self) -> Option<(u64, Hash)> {
+        self.last
+    }
+
+    /// Checks that the last verified block is the certified tip of the chain.
+    pub fn verify_tip(&self, tip: &Tip) -> Result<(), ChainError> {
+        match self.last {
+            Some((index, hash)) if index == tip.last_block_index => {
+                if hash != tip.last_block_hash {
+                    return Err(ChainError::TipHashMismatch {
+                        index,
+                        certified: tip.last_block_hash,
+                        actual: hash,
+                    });
+                }
+                Ok(())
+            }
+            last => Err(ChainError::TipIndexMismatch {
+                certified: tip.last_block_index,
+                actual: last.map(|(index, _)| index),
+            }),
+        }
+    }
+}
+
+fn parent_hash(index: u64, block: &Value) -> Result<Option<Hash>, ChainError> {
+    match block.get("phash") {
+        None => Ok(None),
+        Some(phash) => phash
+            .as_blob()
+            .and_then(|b| Hash::try_from(b).ok())
+            .map(Some)
+            .ok_or(ChainError::MalformedParentHash { index }),
+    }
+}
+
+/// Verifies that the blocks form a valid chain starting at block 0 and,
+/// if the tip is specified, that the last block is the certified tip.
+pub fn verify_chain(blocks: &[BlockWithId], tip: Option<&Tip>) -> Result<(), ChainError> {
+    let mut verifier = ChainVerifier::new();
+    for block in blocks {
+        verifier.push(block)?;
+    }
+    match tip {
+        Some(tip) => verifier.verify_tip(tip),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+
+    fn chain(len: u64) -> Vec<BlockWithId> {
+        let mut blocks: Vec<BlockWithId> = vec![];
+        for id in 0..len {
+            let mut entries = vec![("ts".to_string(), Value::Nat(Nat::from(id)))];
+            if let Some(parent) = blocks.last() {
+                entries.push((
+                    "phash".to_string(),
+                    Value::Blob(hash_value(&parent.block).to_vec()),
+                ));
+            }
+            blocks.push(BlockWithId {
+                id: Nat::from(id),
+                block: Value::Map(entries),
+            });
+        }
+        blocks
+    }
+
+    fn tip(blocks: &[BlockWithId]) -> Tip {
+        Tip {
+            last_block_index: blocks.len() as u64 - 1,
+            last_block_hash: hash_value(&blocks.last().unwrap().block),
+        }
+    }
+
+    fn set_field(block: &mut BlockWithId, key: &str, value: Value) {
+        if let Value::Map(entries) = &mut block.block {
+            entries.retain(|(k, _)| k != key);
+            entries.push((key.to_string(), value));
+        }
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        let blocks = chain(5);
+        verify_chain(&blocks, Some(&tip(&blocks))).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_block() {
+        let mut blocks = chain(5);
+        let tip = tip(&blocks);
+        set_field(&mut blocks[2], "ts", Value::Nat(Nat::from(42u8)));
+        let err = verify_chain(&blocks, Some(&tip)).unwrap_err();
+        assert!(matches!(
+            err,
+            ChainError::ParentHashMismatch { index: 3, .. }
+        ));
+        assert_eq!(err.index(), Some(3));
+    }
+
+    #[test]
+    fn test_first_block_with_parent() {
+        let mut blocks = chain(2);
+        set_field(&mut blocks[0], "phash", Value::Blob(vec![0; 32]));
+        assert_eq!(
+            verify_chain(&blocks, None).unwrap_err(),
+            ChainError::UnexpectedParentHash
+        );
+    }
+
+    #[test]
+    fn test_missing_block() {
+        let mut blocks = chain(4);
+        blocks.remove(2);
+        assert_eq!(verify_chain(&blocks, None).unwrap_err().index(), Some(2));
+    }
+
+    #[test]
+    fn test_tip_mismatch() {
+        let blocks = chain(4);
+        let mut tip = tip(&blocks);
+        tip.last_block_hash[0] ^= 1;
+        assert!(matches!(
+            verify_chain(&blocks, Some(&tip)).unwrap_err(),
+            ChainError::TipHashMismatch { index: 3, .. }
+        ));
+        tip.last_block_index = 4;
+        assert!(matches!(
+            verify_chain(&blocks, Some(&tip)).unwrap_err(),
+            ChainError::TipIndexMismatch {
+                certified: 4,
+                actual: Some(3)
+            }
+        ));
+    }
+
+    #[test]
+    fn test_verify_suffix() {
+        let blocks = chain(6);
+        let mut verifier = ChainVerifier::after(2, hash_value(&blocks[2].block));
+        for block in &blocks[3..] {
+            verifier.push(block).unwrap();
+        }
+        verifier.verify_tip(&tip(&blocks)).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Errors of ledger calls.
+
+use crate::InjectedFault;
+use candid::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// The reject code of a failed call, see
+/// https://internetcomputer.org/docs/current/references/ic-interface-spec#reject-codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RejectCode {
+    SysFatal,
+    SysTransient,
+    DestinationInvalid,
+    CanisterReject,
+    CanisterError,
+}
+
+impl RejectCode {
+    /// Returns the reject code with the specified numeric value.
+    pub fn from_code(code: u64) -> Option<Self> {
+        match code {
+            1 => Some(Self::SysFatal),
+            2 => Some(Self::SysTransient),
+            3 => Some(Self::DestinationInvalid),
+            4 => Some(Self::CanisterReject),
+            5 => Some(Self::CanisterError),
+            _ => None,
+        }
+    }
+}
+
+/// The error returned from [crate::LedgerEnv] calls.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum LedgerCallError {
+    /// The arguments could not be encoded, so the call was not made.
+    #[error("failed to encode the arguments of {method}: {message}")]
+    Encode { method: String, message: String },
+    /// The call failed without a reply from the ledger, for example
+    /// because the replica was unreachable.
+    #[error("call to {method} failed: {message}")]
+    Transport { method: String, message: String },
+    /// The ledger or the system rejected the call.
+    #[error("call to {method} was rejected with code {code:?}: {message}")]
+    Reject {
+        method: String,
+        code: RejectCode,
+        message: String,
+    },
+    /// The reply could not be decoded into the expected type.
+    #[error(
+        "failed to decode the reply of {method} into type {expected}: {message}, bytes: {}",
+        hex::encode(.reply)
+    )]
+    Decode {
+        method: String,
+        expected: String,
+        message: String,
+        reply: Vec<u8>,
+    },
+    /// A [crate::FaultyLedger] failed the call.
+    #[error("{0}")]
+    Injected(#[from] InjectedFault),
+}
+
+impl LedgerCallError {
+    /// Returns the name of the called method.
+    pub fn method(&self) -> &str {
+        match self {
+            Self::Encode { method, .. }
+            | Self::Transport { method, .. }
+            | Self::Reject { method, .. }
+            | Self::Decode { method, .. } => method,
+            Self::Injected(fault) => &fault.method,
+        }
+    }
+}
+
+/// Encodes the arguments of a call to the specified method.
+pub fn encode_call_args<Input>(method: &str, input: Input) -> Result<Vec<u8>, LedgerCallError>
+where
+    Input: ArgumentEncoder + std::fmt::Debug,
+{
+    let debug_inputs = format!("{:?}", input);
+    encode_args(input).map_err(|err| LedgerCallError::Encode {
+        method: method.to_string(),
+        message: format!("{} (arguments {})", err, debug_inputs),
+    })
+}
+
+/// Decodes the reply of a call to the specified method.
+pub fn decode_call_reply<Output>(method: &str, reply: &[u8]) -> Result<Output, LedgerCallError>
+where
+    Output: for<'a> ArgumentDecoder<'a>,
+{
+    decode_args(reply).map_err(|err| LedgerCallError::Decode {
+        method: method.to_string(),
+        expected: std::any::type_name::<Output>().to_string(),
+        message: format!("{:#}", err),
+        reply: reply.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+
+    #[test]
+    fn test_decode_error_keeps_reply() {
+        let reply = encode_args(("not a number",)).unwrap();
+        match decode_call_reply::<(Nat,)>("icrc1_fee", &reply) {
+            Err(LedgerCallError::Decode {
+                method,
+                expected,
+                reply: bytes,
+                ..
+            }) => {
+                assert_eq!(method, "icrc1_fee");
+                assert!(expected.contains("Nat"), "{}", expected);
+                assert_eq!(bytes, reply);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_eq!(
+            decode_call_reply::<(Nat,)>("icrc1_fee", &encode_args((Nat::from(10u8),)).unwrap()),
+            Ok((Nat::from(10u8),))
+        );
+    }
+
+    #[test]
+    fn test_reject_code() {
+        assert_eq!(RejectCode::from_code(4), Some(RejectCode::CanisterReject));
+        assert_eq!(RejectCode::from_code(0), None);
+        assert_eq!(RejectCode::from_code(6), None);
+    }
+}
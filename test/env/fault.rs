@@ -0,0 +1,583 @@
+//! A `LedgerEnv` decorator injecting infrastructure faults.
+
+use crate::{LedgerCallError, LedgerEnv, LedgerFuture, RejectCode, TimeControl};
+use candid::utils::encode_args;
+use candid::{CandidType, Nat, Principal};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+
+/// An infrastructure fault injected into a ledger call.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// The call fails before it reaches the ledger.
+    TransportError,
+    /// The system rejects the call with the specified code without
+    /// executing it.
+    Reject(RejectCode),
+    /// The ledger replies with `TemporarilyUnavailable` without executing
+    /// the call. Only injected into update calls.
+    TemporarilyUnavailable,
+    /// The update call is executed, but the reply never reaches the caller.
+    /// Only injected into update calls.
+    ReplyLost,
+    /// The call succeeds after a delay. A single timer thread shared by all
+    /// faulty ledgers completes the delays, so they cost no thread per call
+    /// but are only as precise as the operating system's sleep.
+    Latency(Duration),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::TransportError => write!(f, "transport error"),
+            Fault::Reject(code) => write!(f, "reject with code {:?}", code),
+            Fault::TemporarilyUnavailable => write!(f, "temporarily unavailable"),
+            Fault::ReplyLost => write!(f, "the update was executed, but the reply was lost"),
+            Fault::Latency(d) => write!(f, "latency of {:?}", d),
+        }
+    }
+}
+
+/// A fault injected by a [FaultyLedger].
+///
+/// Transport errors and lost replies fail the call with
+/// [LedgerCallError::Injected]. Rejects and `TemporarilyUnavailable` replies
+/// look exactly like real ones, use [FaultyLedger::injected] to tell them
+/// apart.
+#[derive(Clone, Debug, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[error("injected fault in call {call} to {method}: {fault}")]
+pub struct InjectedFault {
+    /// The index of the call among all calls made through the decorator.
+    pub call: u64,
+    pub method: String,
+    pub fault: Fault,
+}
+
+/// Determines which calls fail and how.
+///
+/// Each call draws from a random generator seeded with the schedule seed,
+/// so the same seed and the same sequence of calls produce the same faults.
+/// Faults pinned to specific calls with [FaultSchedule::at] take precedence.
+#[derive(Clone, Debug)]
+pub struct FaultSchedule {
+    seed: u64,
+    transport_error: f64,
+    reject: f64,
+    temporarily_unavailable: f64,
+    reply_lost: f64,
+    latency: f64,
+    max_latency: Duration,
+    pinned: BTreeMap<u64, Fault>,
+}
+
+impl FaultSchedule {
+    /// Creates a schedule that does not inject any faults.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            transport_error: 0.0,
+            reject: 0.0,
+            temporarily_unavailable: 0.0,
+            reply_lost: 0.0,
+            latency: 0.0,
+            max_latency: Duration::ZERO,
+            pinned: BTreeMap::new(),
+        }
+    }
+
+    pub fn transport_errors(mut self, probability: f64) -> Self {
+        self.transport_error = probability;
+        self
+    }
+
+    /// Rejects calls with [RejectCode::SysTransient], like a replica
+    /// under load. Pin other codes with [FaultSchedule::at].
+    pub fn rejects(mut self, probability: f64) -> Self {
+        self.reject = probability;
+        self
+    }
+
+    pub fn temporarily_unavailable(mut self, probability: f64) -> Self {
+        self.temporarily_unavailable = probability;
+        self
+    }
+
+    pub fn replies_lost(mut self, probability: f64) -> Self {
+        self.reply_lost = probability;
+        self
+    }
+
+    /// Delays calls by a random duration of at most `max_latency`.
+    pub fn latency(mut self, probability: f64, max_latency: Duration) -> Self {
+        self.latency = probability;
+        self.max_latency = max_latency;
+        self
+    }
+
+    /// Injects the fault into the call with the specified index.
+    pub fn at(mut self, call: u64, fault: Fault) -> Self {
+        self.pinned.insert(call, fault);
+        self
+    }
+}
+
+struct FaultState {
+    schedule: FaultSchedule,
+    rng: StdRng,
+    calls: u64,
+    injected: Vec<InjectedFault>,
+}
+
+impl FaultState {
+    fn next_fault(&mut self, method: &str, is_update: bool) -> Option<InjectedFault> {
+        let call = self.calls;
+        self.calls += 1;
+
+        // Draw for every call so that the faults of later calls do not
+        // depend on which calls are queries.
+        let roll: f64 = self.rng.gen();
+        let latency = self.schedule.max_latency.mul_f64(self.rng.gen());
+
+        let fault = match self.schedule.pinned.get(&call) {
+            Some(fault) => Some(fault.clone()),
+            None => {
+                let s = &self.schedule;
+                let candidates = [
+                    (s.transport_error, Fault::TransportError),
+                    (s.reject, Fault::Reject(RejectCode::SysTransient)),
+                    (s.temporarily_unavailable, Fault::TemporarilyUnavailable),
+                    (s.reply_lost, Fault::ReplyLost),
+                    (s.latency, Fault::Latency(latency)),
+                ];
+                let mut threshold = 0.0;
+                candidates.iter().find_map(|(probability, fault)| {
+                    threshold += probability;
+                    (roll < threshold).then(|| fault.clone())
+                })
+            }
+        };
+
+        let fault = fault.filter(|fault| {
+            is_update || !matches!(fault, Fault::TemporarilyUnavailable | Fault::ReplyLost)
+        })?;
+        let injected = InjectedFault {
+            call,
+            method: method.to_string(),
+            fault,
+        };
+        self.injected.push(injected.clone());
+        Some(injected)
+    }
+}
+
+/// A `LedgerEnv` decorator that injects faults into the calls of the
+/// wrapped environment according to a [FaultSchedule].
+///
+/// Forked environments share the schedule with the original one.
+#[derive(Clone)]
+pub struct FaultyLedger<L> {
+    inner: L,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl<L: LedgerEnv> FaultyLedger<L> {
+    pub fn new(inner: L, schedule: FaultSchedule) -> Self {
+        let rng = StdRng::seed_from_u64(schedule.seed);
+        Self {
+            inner,
+            state: Arc::new(Mutex::new(FaultState {
+                schedule,
+                rng,
+                calls: 0,
+                injected: vec![],
+            })),
+        }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Returns the faults injected so far, including the ones that look
+    /// like real errors to the caller.
+    pub fn injected(&self) -> Vec<InjectedFault> {
+        self.state.lock().unwrap().injected.clone()
+    }
+
+    fn next_fault(&self, method: &str, is_update: bool) -> Option<InjectedFault> {
+        self.state.lock().unwrap().next_fault(method, is_update)
+    }
+}
+
+/// The error type of the `TemporarilyUnavailable` reply. It is a subtype
+/// of the error types of all ICRC-1 and ICRC-2 update methods.
+#[derive(CandidType)]
+enum Unavailable {
+    TemporarilyUnavailable,
+}
+
+fn temporarily_unavailable(injected: InjectedFault) -> Result<Vec<u8>, LedgerCallError> {
+    let reply: (Result<Nat, Unavailable>,) = (Err(Unavailable::TemporarilyUnavailable),);
+    encode_args(reply).map_err(|_| LedgerCallError::Injected(injected))
+}
+
+fn reject(code: RejectCode, injected: InjectedFault) -> LedgerCallError {
+    LedgerCallError::Reject {
+        method: injected.method.clone(),
+        code,
+        message: injected.to_string(),
+    }
+}
+
+impl<L: LedgerEnv> LedgerEnv for FaultyLedger<L> {
+    fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.fork(),
+            state: self.state.clone(),
+        }
+    }
+
+    fn with_label(&self, label: &str) -> Option<Self> {
+        self.inner.with_label(label).map(|inner| Self {
+            inner,
+            state: self.state.clone(),
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        self.inner.with_canister(canister_id).map(|inner| Self {
+            inner,
+            state: self.state.clone(),
+        })
+    }
+
+    fn principal(&self) -> Principal {
+        self.inner.principal()
+    }
+
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        self.inner.time_control()
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            let injected = match self.next_fault(method, false) {
+                None => return self.inner.query_raw(method, arg).await,
+                Some(injected) => injected,
+            };
+            match injected.fault {
+                Fault::Latency(latency) => {
+                    Delay::new(latency).await;
+                    self.inner.query_raw(method, arg).await
+                }
+                Fault::Reject(code) => Err(reject(code, injected)),
+                _ => Err(injected.into()),
+            }
+        })
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            let injected = match self.next_fault(method, true) {
+                None => return self.inner.update_raw(method, arg).await,
+                Some(injected) => injected,
+            };
+            match injected.fault {
+                Fault::Latency(latency) => {
+                    Delay::new(latency).await;
+                    self.inner.update_raw(method, arg).await
+                }
+                Fault::ReplyLost => {
+                    let _ignored = self.inner.update_raw(method, arg).await;
+                    Err(injected.into())
+                }
+                Fault::TemporarilyUnavailable => temporarily_unavailable(injected),
+                Fault::Reject(code) => Err(reject(code, injected)),
+                Fault::TransportError => Err(injected.into()),
+            }
+        })
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        self.inner.canister_metadata(name)
+    }
+}
+
+type DelayState = Arc<Mutex<(bool, Option<Waker>)>>;
+
+/// A future completing after a delay, independent of the async runtime.
+struct Delay {
+    state: DelayState,
+}
+
+impl Delay {
+    fn new(duration: Duration) -> Self {
+        let state = Arc::new(Mutex::new((false, None::<Waker>)));
+        timer()
+            .lock()
+            .unwrap()
+            .send((Instant::now() + duration, state.clone()))
+            .expect("the timer thread never exits");
+        Self { state }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.0 {
+            Poll::Ready(())
+        } else {
+            state.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+/// Returns the channel of the thread completing all delays, starting the
+/// thread on first use.
+fn timer() -> &'static Mutex<Sender<(Instant, DelayState)>> {
+    static TIMER: OnceLock<Mutex<Sender<(Instant, DelayState)>>> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<(Instant, DelayState)>();
+        std::thread::spawn(move || {
+            // Keyed by the deadline and a sequence number, since delays
+            // may share a deadline.
+            let mut pending = BTreeMap::new();
+            let mut sequence = 0u64;
+            loop {
+                let now = Instant::now();
+                while let Some(entry) = pending.first_entry() {
+                    let (deadline, _) = *entry.key();
+                    if deadline > now {
+                        break;
+                    }
+                    let state: DelayState = entry.remove();
+                    let mut state = state.lock().unwrap();
+                    state.0 = true;
+                    if let Some(waker) = state.1.take() {
+                        waker.wake();
+                    }
+                }
+                let received = match pending.keys().next() {
+                    Some(&(deadline, _)) => receiver.recv_timeout(deadline - now),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((deadline, state)) => {
+                        pending.insert((deadline, sequence), state);
+                        sequence += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        Mutex::new(sender)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApproveError, TransferError};
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Counts executed updates and replies `Ok(42)` to every call.
+    #[derive(Clone, Default)]
+    struct CountingLedger {
+        updates: Arc<AtomicU64>,
+    }
+
+    impl LedgerEnv for CountingLedger {
+        fn fork(&self) -> Self {
+            self.clone()
+        }
+
+        fn principal(&self) -> Principal {
+            Principal::anonymous()
+        }
+
+        fn time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+
+        fn query_raw<'a>(
+            &'a self,
+            _method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                let reply: (Result<Nat, TransferError>,) = (Ok(Nat::from(42u8)),);
+                Ok(encode_args(reply).unwrap())
+            })
+        }
+
+        fn update_raw<'a>(
+            &'a self,
+            method: &'a str,
+            arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                self.updates.fetch_add(1, Ordering::Relaxed);
+                self.query_raw(method, arg).await
+            })
+        }
+    }
+
+    type TransferResult = Result<(Result<Nat, TransferError>,), LedgerCallError>;
+
+    #[test]
+    fn test_pinned_faults() {
+        let inner = CountingLedger::default();
+        let ledger = FaultyLedger::new(
+            inner.clone(),
+            FaultSchedule::new(0)
+                .at(0, Fault::TransportError)
+                .at(1, Fault::ReplyLost)
+                .at(2, Fault::TemporarilyUnavailable)
+                .at(3, Fault::Latency(Duration::from_millis(1))),
+        );
+        block_on(async {
+            let err = (ledger.update("icrc1_transfer", ()).await as TransferResult).unwrap_err();
+            assert!(matches!(
+                err,
+                LedgerCallError::Injected(InjectedFault {
+                    fault: Fault::TransportError,
+                    ..
+                })
+            ));
+            assert_eq!(inner.updates.load(Ordering::Relaxed), 0);
+
+            let err = (ledger.update("icrc1_transfer", ()).await as TransferResult).unwrap_err();
+            assert!(matches!(err, LedgerCallError::Injected(_)));
+            assert_eq!(
+                inner.updates.load(Ordering::Relaxed),
+                1,
+                "lost replies must execute the update"
+            );
+
+            let (result,): (Result<Nat, ApproveError>,) =
+                ledger.update("icrc2_approve", ()).await.unwrap();
+            assert_eq!(result, Err(ApproveError::TemporarilyUnavailable));
+            assert_eq!(inner.updates.load(Ordering::Relaxed), 1);
+
+            let (result,): (Result<Nat, TransferError>,) =
+                ledger.update("icrc1_transfer", ()).await.unwrap();
+            assert_eq!(result, Ok(Nat::from(42u8)));
+            assert_eq!(inner.updates.load(Ordering::Relaxed), 2);
+        });
+        assert_eq!(ledger.injected().len(), 4);
+    }
+
+    #[test]
+    fn test_rejects_look_real() {
+        let ledger = FaultyLedger::new(
+            CountingLedger::default(),
+            FaultSchedule::new(0)
+                .at(0, Fault::Reject(RejectCode::CanisterError))
+                .at(1, Fault::Reject(RejectCode::SysTransient)),
+        );
+        block_on(async {
+            let err = (ledger.update("icrc1_transfer", ()).await as TransferResult).unwrap_err();
+            assert!(matches!(
+                err,
+                LedgerCallError::Reject {
+                    code: RejectCode::CanisterError,
+                    ..
+                }
+            ));
+            let err = (ledger.query("icrc1_balance_of", ()).await as TransferResult).unwrap_err();
+            assert!(matches!(
+                err,
+                LedgerCallError::Reject {
+                    code: RejectCode::SysTransient,
+                    ..
+                }
+            ));
+        });
+        let injected = ledger.injected();
+        assert_eq!(injected.len(), 2);
+        assert_eq!(injected[1].method, "icrc1_balance_of");
+    }
+
+    #[test]
+    fn test_concurrent_delays() {
+        let ledger = FaultyLedger::new(
+            CountingLedger::default(),
+            FaultSchedule::new(0).latency(1.0, Duration::from_millis(20)),
+        );
+        block_on(async {
+            let calls = (0..100).map(|_| ledger.update("icrc1_transfer", ()));
+            for result in futures::future::join_all(calls).await {
+                let (result,) = (result as TransferResult).unwrap();
+                assert_eq!(result, Ok(Nat::from(42u8)));
+            }
+        });
+        assert_eq!(ledger.injected().len(), 100);
+    }
+
+    #[test]
+    fn test_update_only_faults_skip_queries() {
+        let ledger = FaultyLedger::new(
+            CountingLedger::default(),
+            FaultSchedule::new(0).replies_lost(1.0),
+        );
+        block_on(async {
+            let (result,) = (ledger.query("icrc1_balance_of", ()).await as TransferResult).unwrap();
+            assert_eq!(result, Ok(Nat::from(42u8)));
+            (ledger.update("icrc1_transfer", ()).await as TransferResult).unwrap_err();
+        });
+    }
+
+    #[test]
+    fn test_same_seed_same_faults() {
+        let run = |seed: u64| {
+            let ledger = FaultyLedger::new(
+                CountingLedger::default(),
+                FaultSchedule::new(seed)
+                    .transport_errors(0.2)
+                    .rejects(0.2)
+                    .temporarily_unavailable(0.2),
+            );
+            block_on(async {
+                for _ in 0..50 {
+                    let _: TransferResult = ledger.update("icrc1_transfer", ()).await;
+                }
+            });
+            ledger.injected()
+        };
+        let faults = run(7);
+        assert!(!faults.is_empty());
+        assert_eq!(faults, run(7));
+        assert_ne!(faults, run(8));
+    }
+}
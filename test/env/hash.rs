@@ -0,0 +1,216 @@
+//! Representation-independent hashing of ICRC-3 values.
+//!
+//! See `standards/ICRC-3/HASHINGVALUES.md` for the specification.
+
+use crate::Value;
+use candid::{Int, Nat};
+use sha2::{Digest, Sha256};
+
+/// A SHA-256 hash.
+pub type Hash = [u8; 32];
+
+/// Computes the representation-independent hash of an ICRC-3 value.
+pub fn hash_value(value: &Value) -> Hash {
+    match value {
+        Value::Nat(n) => hash_nat(n),
+        Value::Int(i) => hash_int(i),
+        Value::Text(t) => hash_bytes(t.as_bytes()),
+        Value::Blob(b) => hash_bytes(b),
+        Value::Array(values) => {
+            let mut hasher = ArrayHasher::new();
+            for value in values {
+                hasher.push(value);
+            }
+            hasher.finish()
+        }
+        Value::Map(entries) => {
+            let mut hasher = MapHasher::new();
+            for (key, value) in entries {
+                hasher.insert(key, value);
+            }
+            hasher.finish()
+        }
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+fn hash_nat(n: &Nat) -> Hash {
+    let mut buf = vec![];
+    n.encode(&mut buf).expect("writing to a vector never fails");
+    hash_bytes(&buf)
+}
+
+fn hash_int(i: &Int) -> Hash {
+    let mut buf = vec![];
+    i.encode(&mut buf).expect("writing to a vector never fails");
+    hash_bytes(&buf)
+}
+
+/// Computes the hash of an `Array` value element by element, so that
+/// the whole array does not need to be held in memory at once.
+#[derive(Clone, Default)]
+pub struct ArrayHasher {
+    hasher: Sha256,
+}
+
+impl ArrayHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the next element of the array.
+    pub fn push(&mut self, value: &Value) {
+        self.push_hash(&hash_value(value));
+    }
+
+    /// Appends the next element of the array given its hash.
+    pub fn push_hash(&mut self, hash: &Hash) {
+        self.hasher.update(hash);
+    }
+
+    pub fn finish(self) -> Hash {
+        self.hasher.finalize().into()
+    }
+}
+
+/// Computes the hash of a `Map` value entry by entry, so that the
+/// entries can be hashed as soon as they are available.
+///
+/// Only the 64-byte hashes of the entries are kept until [MapHasher::finish]
+/// sorts and combines them.
+#[derive(Clone, Default)]
+pub struct MapHasher {
+    entries: Vec<(Hash, Hash)>,
+}
+
+impl MapHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an entry to the map.
+    pub fn insert(&mut self, key: &str, value: &Value) {
+        self.insert_hash(key, hash_value(value));
+    }
+
+    /// Adds an entry to the map given the hash of its value.
+    pub fn insert_hash(&mut self, key: &str, value_hash: Hash) {
+        self.entries.push((hash_bytes(key.as_bytes()), value_hash));
+    }
+
+    pub fn finish(mut self) -> Hash {
+        self.entries.sort();
+        let mut hasher = Sha256::new();
+        for (key_hash, value_hash) in self.entries.iter() {
+            hasher.update(key_hash);
+            hasher.update(value_hash);
+        }
+        hasher.finalize().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(value: Value, expected: &str) {
+        assert_eq!(hex::encode(hash_value(&value)), expected, "{:?}", value);
+    }
+
+    #[test]
+    fn test_nat() {
+        check(
+            Value::Nat(Nat::from(42u64)),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1",
+        );
+    }
+
+    #[test]
+    fn test_int() {
+        check(
+            Value::Int(Int::from(-42i64)),
+            "de5a6f78116eca62d7fc5ce159d23ae6b889b365a1739ad2cf36f925a140d0cc",
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        check(
+            Value::from("Hello, World!"),
+            "dffd6021bb2bd5b0af676290809ec3a53191dd81c7f70a4b28688a362182986f",
+        );
+    }
+
+    #[test]
+    fn test_blob() {
+        check(
+            Value::Blob(vec![1, 2, 3, 4]),
+            "9f64a747e1b97f131fabb6b447296c9b6f0201e79fb3c5356e6c77e89b6a806a",
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        check(
+            Value::Array(vec![
+                Value::Nat(Nat::from(3u64)),
+                Value::from("foo"),
+                Value::Blob(vec![5, 6]),
+            ]),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6",
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let map = Value::Map(vec![
+            (
+                "from".to_string(),
+                Value::Blob(
+                    hex::decode("00abcdef0012340056789a00bcdef000012345678900abcdef01").unwrap(),
+                ),
+            ),
+            (
+                "to".to_string(),
+                Value::Blob(
+                    hex::decode("00ab0def0012340056789a00bcdef000012345678900abcdef01").unwrap(),
+                ),
+            ),
+            ("amount".to_string(), Value::Nat(Nat::from(42u64))),
+            (
+                "created_at".to_string(),
+                Value::Nat(Nat::from(1699218263u64)),
+            ),
+            ("memo".to_string(), Value::Nat(Nat::from(0u64))),
+        ]);
+        check(
+            map.clone(),
+            "c56ece650e1de4269c5bdeff7875949e3e2033f85b2d193c2ff4f7f78bdcfc75",
+        );
+
+        // The hash must not depend on the order of the entries.
+        let mut entries = map.as_map().unwrap().to_vec();
+        entries.reverse();
+        check(
+            Value::Map(entries),
+            "c56ece650e1de4269c5bdeff7875949e3e2033f85b2d193c2ff4f7f78bdcfc75",
+        );
+    }
+
+    #[test]
+    fn test_streaming_matches_hash_value() {
+        let elements = vec![
+            Value::Nat(Nat::from(3u64)),
+            Value::from("foo"),
+            Value::Blob(vec![5, 6]),
+        ];
+        let mut hasher = ArrayHasher::new();
+        for e in elements.iter() {
+            hasher.push_hash(&hash_value(e));
+        }
+        assert_eq!(hasher.finish(), hash_value(&Value::Array(elements)));
+    }
+}
@@ -0,0 +1,704 @@
+//! A port of the reference ledger implementation in `ref/ICRC1.mo`.
+
+use candid::{CandidType, Int, Nat, Principal};
+use icrc1_test_env::{
+    Account, Allowance, ApproveError, Subaccount, SupportedStandard, TransferError,
+    TransferFromError, Value,
+};
+use serde::Deserialize;
+
+const MAX_MEMO_SIZE: usize = 32;
+const PERMITTED_DRIFT_NANOS: u64 = 60_000_000_000;
+const TRANSACTION_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const DEFAULT_SUBACCOUNT: Subaccount = [0; 32];
+
+/// The initialization arguments of the ledger.
+#[derive(Clone, Debug)]
+pub struct InitArgs {
+    pub minting_account: Account,
+    pub initial_mints: Vec<(Account, Nat)>,
+    pub token_name: String,
+    pub token_symbol: String,
+    pub decimals: u8,
+    pub transfer_fee: Nat,
+}
+
+/// Deliberate deviations from the standard, used to check that the test
+/// suite detects faulty ledgers. All defects are disabled by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Defects {
+    /// Never report duplicate transactions.
+    pub skip_deduplication: bool,
+    /// Report the index of the transaction following the original one in
+    /// `Duplicate` errors.
+    pub wrong_duplicate_of: bool,
+    /// Charge one token more than the advertised fee for transfers.
+    pub charge_wrong_fee: bool,
+    /// Accept approvals expiring in the past and never expire allowances.
+    pub ignore_expires_at: bool,
+    /// Treat `subaccount = null` and the default subaccount as different accounts.
+    pub skip_subaccount_normalization: bool,
+    /// Accept memos longer than the maximum memo length.
+    pub accept_long_memos: bool,
+    /// Debit the default account of the caller regardless of `from_subaccount`.
+    pub ignore_from_subaccount: bool,
+    /// Declare `icrc1_balance_of` as an update method in the Candid interface.
+    pub balance_of_as_update: bool,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ApproveArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct TransferFromArg {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct AllowanceArg {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TransferSource {
+    Init,
+    Icrc1Transfer,
+    Icrc2TransferFrom,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct TransferTx {
+    spender: Account,
+    source: TransferSource,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ApproveTx {
+    from: Account,
+    spender: Account,
+    amount: Nat,
+    expires_at: Option<u64>,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+enum Operation {
+    Approve(ApproveTx),
+    Transfer(TransferTx),
+    Burn(TransferTx),
+    Mint(TransferTx),
+}
+
+#[derive(Clone, Debug)]
+struct Transaction {
+    operation: Operation,
+    /// Effective fee for this transaction.
+    fee: Nat,
+    timestamp: u64,
+}
+
+/// The ledger state: the configuration and the transaction log.
+///
+/// Balances and allowances are computed from the log on every call, just
+/// like in the Motoko reference implementation.
+pub struct Ledger {
+    init: InitArgs,
+    log: Vec<Transaction>,
+    now: u64,
+    defects: Defects,
+}
+
+fn to_nat(i: Int) -> Nat {
+    Nat(i.0.to_biguint().unwrap_or_default())
+}
+
+fn zero() -> Nat {
+    Nat::from(0u8)
+}
+
+impl Ledger {
+    pub fn new(init: InitArgs, now: u64) -> Self {
+        let log = init
+            .initial_mints
+            .iter()
+            .map(|(account, amount)| Transaction {
+                operation: Operation::Mint(TransferTx {
+                    spender: init.minting_account.clone(),
+                    source: TransferSource::Init,
+                    from: init.minting_account.clone(),
+                    to: account.clone(),
+                    amount: amount.clone(),
+                    fee: None,
+                    memo: None,
+                    created_at_time: Some(now),
+                }),
+                fee: zero(),
+                timestamp: now,
+            })
+            .collect();
+        Self {
+            init,
+            log,
+            now,
+            defects: Defects::default(),
+        }
+    }
+
+    pub fn time(&self) -> u64 {
+        self.now
+    }
+
+    pub fn set_time(&mut self, now: u64) {
+        self.now = now;
+    }
+
+    pub fn set_defects(&mut self, defects: Defects) {
+        self.defects = defects;
+    }
+
+    /// Returns the Candid interface of the ledger, its `candid:service`
+    /// metadata.
+    pub fn candid_service(&self) -> String {
+        let did = include_str!("ledger.did");
+        if self.defects.balance_of_as_update {
+            did.replace(
+                "icrc1_balance_of : (Account) -> (nat) query;",
+                "icrc1_balance_of : (Account) -> (nat);",
+            )
+        } else {
+            did.to_string()
+        }
+    }
+
+    /// Checks whether two accounts are semantically equal.
+    fn accounts_equal(&self, lhs: &Account, rhs: &Account) -> bool {
+        if self.defects.skip_subaccount_normalization {
+            return lhs == rhs;
+        }
+        lhs.owner == rhs.owner
+            && lhs.subaccount.unwrap_or(DEFAULT_SUBACCOUNT)
+                == rhs.subaccount.unwrap_or(DEFAULT_SUBACCOUNT)
+    }
+
+    fn duplicate_of(&self, txid: usize) -> Nat {
+        if self.defects.wrong_duplicate_of {
+            Nat::from(txid + 1)
+        } else {
+            Nat::from(txid)
+        }
+    }
+
+    fn balance(&self, account: &Account) -> Nat {
+        let mut sum = Int::from(0);
+        for tx in self.log.iter() {
+            match &tx.operation {
+                Operation::Burn(args) => {
+                    if self.accounts_equal(&args.from, account) {
+                        sum -= Int::from(args.amount.clone());
+                    }
+                }
+                Operation::Mint(args) => {
+                    if self.accounts_equal(&args.to, account) {
+                        sum += Int::from(args.amount.clone());
+                    }
+                }
+                Operation::Transfer(args) => {
+                    if self.accounts_equal(&args.from, account) {
+                        sum -= Int::from(args.amount.clone() + tx.fee.clone());
+                    }
+                    if self.accounts_equal(&args.to, account) {
+                        sum += Int::from(args.amount.clone());
+                    }
+                }
+                Operation::Approve(args) => {
+                    if self.accounts_equal(&args.from, account) {
+                        sum -= Int::from(tx.fee.clone());
+                    }
+                }
+            }
+        }
+        to_nat(sum)
+    }
+
+    fn total_supply(&self) -> Nat {
+        let mut total = Int::from(0);
+        for tx in self.log.iter() {
+            match &tx.operation {
+                Operation::Burn(args) => total -= Int::from(args.amount.clone()),
+                Operation::Mint(args) => total += Int::from(args.amount.clone()),
+                Operation::Transfer(_) | Operation::Approve(_) => {
+                    total -= Int::from(tx.fee.clone())
+                }
+            }
+        }
+        to_nat(total)
+    }
+
+    /// Finds a transfer in the transaction log.
+    fn find_transfer(&self, transfer: &TransferTx) -> Option<usize> {
+        if self.defects.skip_deduplication {
+            return None;
+        }
+        self.log.iter().position(|tx| match &tx.operation {
+            Operation::Burn(args) | Operation::Mint(args) | Operation::Transfer(args) => {
+                args == transfer
+            }
+            Operation::Approve(_) => false,
+        })
+    }
+
+    /// Finds an approval in the transaction log.
+    fn find_approval(&self, approval: &ApproveTx) -> Option<usize> {
+        if self.defects.skip_deduplication {
+            return None;
+        }
+        self.log.iter().position(|tx| match &tx.operation {
+            Operation::Approve(args) => args == approval,
+            _ => false,
+        })
+    }
+
+    fn allowance(&self, account: &Account, spender: &Account) -> Allowance {
+        let mut allowance = Int::from(0);
+        let mut last_expires_at: Option<u64> = None;
+
+        for tx in self.log.iter() {
+            // Reset expired approvals, if any.
+            if let Some(expires_at) = last_expires_at {
+                if expires_at < tx.timestamp && !self.defects.ignore_expires_at {
+                    allowance = Int::from(0);
+                    last_expires_at = None;
+                }
+            }
+            match &tx.operation {
+                Operation::Approve(args) => {
+                    if self.accounts_equal(&args.from, account)
+                        && self.accounts_equal(&args.spender, spender)
+                    {
+                        allowance = Int::from(args.amount.clone());
+                        last_expires_at = args.expires_at;
+                    }
+                }
+                Operation::Transfer(args) | Operation::Burn(args) => {
+                    if args.source == TransferSource::Icrc2TransferFrom
+                        && args.spender.owner != args.from.owner
+                        && self.accounts_equal(&args.from, account)
+                        && self.accounts_equal(&args.spender, spender)
+                    {
+                        allowance -= Int::from(args.amount.clone() + tx.fee.clone());
+                    }
+                }
+                Operation::Mint(_) => {}
+            }
+        }
+
+        match last_expires_at {
+            Some(expires_at) if expires_at < self.now && !self.defects.ignore_expires_at => {
+                Allowance {
+                    allowance: zero(),
+                    expires_at: None,
+                }
+            }
+            expires_at => Allowance {
+                allowance: to_nat(allowance),
+                expires_at,
+            },
+        }
+    }
+
+    fn check_tx_time(&self, created_at_time: Option<u64>) -> Result<(), TransferError> {
+        let now = self.now;
+        let tx_time = created_at_time.unwrap_or(now);
+
+        if tx_time > now && tx_time - now > PERMITTED_DRIFT_NANOS {
+            return Err(TransferError::CreatedInFuture { ledger_time: now });
+        }
+        if tx_time < now && now - tx_time > TRANSACTION_WINDOW_NANOS + PERMITTED_DRIFT_NANOS {
+            return Err(TransferError::TooOld);
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, operation: Operation, fee: Nat) -> Nat {
+        self.log.push(Transaction {
+            operation,
+            fee,
+            timestamp: self.now,
+        });
+        Nat::from(self.log.len() - 1)
+    }
+
+    fn classify_transfer(&self, transfer: TransferTx) -> Result<(Operation, Nat), TransferError> {
+        let minter = &self.init.minting_account;
+
+        if transfer.created_at_time.is_some() {
+            if let Some(txid) = self.find_transfer(&transfer) {
+                return Err(TransferError::Duplicate {
+                    duplicate_of: self.duplicate_of(txid),
+                });
+            }
+        }
+
+        let result = if self.accounts_equal(&transfer.from, minter) {
+            if transfer.fee.clone().unwrap_or_else(zero) != 0u64 {
+                return Err(TransferError::BadFee {
+                    expected_fee: zero(),
+                });
+            }
+            (Operation::Mint(transfer), zero())
+        } else if self.accounts_equal(&transfer.to, minter) {
+            if transfer.fee.clone().unwrap_or_else(zero) != 0u64 {
+                return Err(TransferError::BadFee {
+                    expected_fee: zero(),
+                });
+            }
+            if transfer.amount < self.init.transfer_fee {
+                return Err(TransferError::BadBurn {
+                    min_burn_amount: self.init.transfer_fee.clone(),
+                });
+            }
+            let balance = self.balance(&transfer.from);
+            if balance < transfer.amount {
+                return Err(TransferError::InsufficientFunds { balance });
+            }
+            (Operation::Burn(transfer), zero())
+        } else {
+            let effective_fee = self.init.transfer_fee.clone();
+            if transfer
+                .fee
+                .clone()
+                .unwrap_or_else(|| effective_fee.clone())
+                != effective_fee
+            {
+                return Err(TransferError::BadFee {
+                    expected_fee: effective_fee,
+                });
+            }
+            let charged_fee = if self.defects.charge_wrong_fee {
+                effective_fee + Nat::from(1u8)
+            } else {
+                effective_fee
+            };
+            let balance = self.balance(&transfer.from);
+            if balance < transfer.amount.clone() + charged_fee.clone() {
+                return Err(TransferError::InsufficientFunds { balance });
+            }
+            (Operation::Transfer(transfer), charged_fee)
+        };
+        Ok(result)
+    }
+
+    fn apply_transfer(&mut self, transfer: TransferTx) -> Result<Nat, TransferError> {
+        self.check_tx_time(transfer.created_at_time)?;
+        let (operation, fee) = self.classify_transfer(transfer)?;
+        Ok(self.record(operation, fee))
+    }
+
+    pub fn icrc1_transfer(
+        &mut self,
+        caller: Principal,
+        arg: TransferArg,
+    ) -> Result<Result<Nat, TransferError>, String> {
+        self.validate_memo(&arg.memo)?;
+        let from = Account {
+            owner: caller,
+            subaccount: if self.defects.ignore_from_subaccount {
+                None
+            } else {
+                arg.from_subaccount
+            },
+        };
+        Ok(self.apply_transfer(TransferTx {
+            spender: from.clone(),
+            source: TransferSource::Icrc1Transfer,
+            from,
+            to: arg.to,
+            amount: arg.amount,
+            fee: arg.fee,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+        }))
+    }
+
+    pub fn icrc1_balance_of(&self, account: Account) -> Nat {
+        self.balance(&account)
+    }
+
+    pub fn icrc1_total_supply(&self) -> Nat {
+        self.total_supply()
+    }
+
+    pub fn icrc1_minting_account(&self) -> Option<Account> {
+        Some(self.init.minting_account.clone())
+    }
+
+    pub fn icrc1_name(&self) -> String {
+        self.init.token_name.clone()
+    }
+
+    pub fn icrc1_symbol(&self) -> String {
+        self.init.token_symbol.clone()
+    }
+
+    pub fn icrc1_decimals(&self) -> u8 {
+        self.init.decimals
+    }
+
+    pub fn icrc1_fee(&self) -> Nat {
+        self.init.transfer_fee.clone()
+    }
+
+    pub fn icrc1_metadata(&self) -> Vec<(String, Value)> {
+        vec![
+            ("icrc1:name".to_string(), Value::Text(self.icrc1_name())),
+            ("icrc1:symbol".to_string(), Value::Text(self.icrc1_symbol())),
+            (
+                "icrc1:decimals".to_string(),
+                Value::Nat(Nat::from(self.init.decimals)),
+            ),
+            ("icrc1:fee".to_string(), Value::Nat(self.icrc1_fee())),
+            (
+                "icrc1:max_memo_length".to_string(),
+                Value::Nat(Nat::from(MAX_MEMO_SIZE)),
+            ),
+        ]
+    }
+
+    pub fn icrc1_supported_standards(&self) -> Vec<SupportedStandard> {
+        vec![
+            SupportedStandard {
+                name: "ICRC-1".to_string(),
+                url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1".to_string(),
+            },
+            SupportedStandard {
+                name: "ICRC-2".to_string(),
+                url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2".to_string(),
+            },
+        ]
+    }
+
+    pub fn icrc2_approve(
+        &mut self,
+        caller: Principal,
+        arg: ApproveArg,
+    ) -> Result<Result<Nat, ApproveError>, String> {
+        self.validate_memo(&arg.memo)?;
+        Ok(self.approve(caller, arg))
+    }
+
+    fn approve(&mut self, caller: Principal, arg: ApproveArg) -> Result<Nat, ApproveError> {
+        self.check_tx_time(arg.created_at_time)
+            .map_err(to_approve_error)?;
+
+        let approver = Account {
+            owner: caller,
+            subaccount: arg.from_subaccount,
+        };
+        let approval = ApproveTx {
+            from: approver.clone(),
+            spender: arg.spender,
+            amount: arg.amount,
+            expires_at: arg.expires_at,
+            fee: arg.fee,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+        };
+
+        if arg.created_at_time.is_some() {
+            if let Some(txid) = self.find_approval(&approval) {
+                return Err(ApproveError::Duplicate {
+                    duplicate_of: self.duplicate_of(txid),
+                });
+            }
+        }
+
+        if let Some(expires_at) = arg.expires_at {
+            if expires_at < self.now && !self.defects.ignore_expires_at {
+                return Err(ApproveError::Expired {
+                    ledger_time: self.now,
+                });
+            }
+        }
+
+        let effective_fee = self.init.transfer_fee.clone();
+        if approval
+            .fee
+            .clone()
+            .unwrap_or_else(|| effective_fee.clone())
+            != effective_fee
+        {
+            return Err(ApproveError::BadFee {
+                expected_fee: effective_fee,
+            });
+        }
+
+        if let Some(expected_allowance) = arg.expected_allowance {
+            let current = self.allowance(&approver, &approval.spender);
+            if current.allowance != expected_allowance {
+                return Err(ApproveError::AllowanceChanged {
+                    current_allowance: current.allowance,
+                });
+            }
+        }
+
+        let balance = self.balance(&approver);
+        if balance < effective_fee {
+            return Err(ApproveError::InsufficientFunds { balance });
+        }
+
+        Ok(self.record(Operation::Approve(approval), effective_fee))
+    }
+
+    pub fn icrc2_transfer_from(
+        &mut self,
+        caller: Principal,
+        arg: TransferFromArg,
+    ) -> Result<Result<Nat, TransferFromError>, String> {
+        self.validate_memo(&arg.memo)?;
+        Ok(self.transfer_from(caller, arg))
+    }
+
+    fn transfer_from(
+        &mut self,
+        caller: Principal,
+        arg: TransferFromArg,
+    ) -> Result<Nat, TransferFromError> {
+        let spender = Account {
+            owner: caller,
+            subaccount: arg.spender_subaccount,
+        };
+        let amount = arg.amount.clone();
+        let transfer = TransferTx {
+            spender: spender.clone(),
+            source: TransferSource::Icrc2TransferFrom,
+            from: arg.from,
+            to: arg.to,
+            amount: arg.amount,
+            fee: arg.fee,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+        };
+
+        if caller == transfer.from.owner {
+            return self
+                .apply_transfer(transfer)
+                .map_err(to_transfer_from_error);
+        }
+
+        self.check_tx_time(transfer.created_at_time)
+            .map_err(to_transfer_from_error)?;
+        let from = transfer.from.clone();
+        let (operation, fee) = self
+            .classify_transfer(transfer)
+            .map_err(to_transfer_from_error)?;
+
+        let allowance = self.allowance(&from, &spender);
+        if allowance.allowance < amount + fee.clone() {
+            return Err(TransferFromError::InsufficientAllowance {
+                allowance: allowance.allowance,
+            });
+        }
+
+        Ok(self.record(operation, fee))
+    }
+
+    pub fn icrc2_allowance(&self, arg: AllowanceArg) -> Allowance {
+        self.allowance(&arg.account, &arg.spender)
+    }
+
+    fn validate_memo(&self, memo: &Option<Vec<u8>>) -> Result<(), String> {
+        match memo {
+            Some(memo) if memo.len() > MAX_MEMO_SIZE && !self.defects.accept_long_memos => {
+                Err(format!(
+                    "the memo is {} bytes long, the maximum is {}",
+                    memo.len(),
+                    MAX_MEMO_SIZE
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn to_approve_error(err: TransferError) -> ApproveError {
+    match err {
+        TransferError::TooOld => ApproveError::TooOld,
+        TransferError::CreatedInFuture { ledger_time } => {
+            ApproveError::CreatedInFuture { ledger_time }
+        }
+        TransferError::Duplicate { duplicate_of } => ApproveError::Duplicate { duplicate_of },
+        TransferError::BadFee { expected_fee } => ApproveError::BadFee { expected_fee },
+        TransferError::InsufficientFunds { balance } => ApproveError::InsufficientFunds { balance },
+        TransferError::TemporarilyUnavailable => ApproveError::TemporarilyUnavailable,
+        TransferError::GenericError {
+            error_code,
+            message,
+        } => ApproveError::GenericError {
+            error_code,
+            message,
+        },
+        TransferError::BadBurn { min_burn_amount } => ApproveError::GenericError {
+            error_code: Nat::from(0u8),
+            message: format!("the minimal burn amount is {}", min_burn_amount),
+        },
+    }
+}
+
+fn to_transfer_from_error(err: TransferError) -> TransferFromError {
+    match err {
+        TransferError::BadFee { expected_fee } => TransferFromError::BadFee { expected_fee },
+        TransferError::BadBurn { min_burn_amount } => {
+            TransferFromError::BadBurn { min_burn_amount }
+        }
+        TransferError::InsufficientFunds { balance } => {
+            TransferFromError::InsufficientFunds { balance }
+        }
+        TransferError::TooOld => TransferFromError::TooOld,
+        TransferError::CreatedInFuture { ledger_time } => {
+            TransferFromError::CreatedInFuture { ledger_time }
+        }
+        TransferError::Duplicate { duplicate_of } => TransferFromError::Duplicate { duplicate_of },
+        TransferError::TemporarilyUnavailable => TransferFromError::TemporarilyUnavailable,
+        TransferError::GenericError {
+            error_code,
+            message,
+        } => TransferFromError::GenericError {
+            error_code,
+            message,
+        },
+    }
+}
@@ -0,0 +1,476 @@
+use candid::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
+use candid::Principal;
+use icrc1_test_env::{Account, LedgerCallError, LedgerEnv, LedgerFuture, RejectCode, TimeControl};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+mod ledger;
+
+pub use ledger::{
+    AllowanceArg, ApproveArg, Defects, InitArgs, Ledger, TransferArg, TransferFromArg,
+};
+
+fn new_principal(n: u64) -> Principal {
+    let mut bytes = n.to_le_bytes().to_vec();
+    bytes.push(0xfe);
+    bytes.push(0x01);
+    Principal::try_from_slice(&bytes[..]).unwrap()
+}
+
+fn time_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("the time is before the UNIX epoch")
+        .as_nanos() as u64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CallKind {
+    Query,
+    Update,
+}
+
+/// A ledger environment backed by an in-memory port of `ref/ICRC1.mo`.
+///
+/// Calls are encoded and decoded with Candid exactly as they would be for
+/// a real canister, but are executed synchronously in the current process.
+/// The ledger time does not move unless it's changed explicitly.
+#[derive(Clone)]
+pub struct InMemoryLedger {
+    counter: Arc<AtomicU64>,
+    ledger: Arc<Mutex<Ledger>>,
+    caller: Principal,
+    canister_id: Principal,
+}
+
+impl LedgerEnv for InMemoryLedger {
+    fn fork(&self) -> Self {
+        Self {
+            counter: self.counter.clone(),
+            ledger: self.ledger.clone(),
+            caller: new_principal(self.counter.fetch_add(1, Ordering::Relaxed)),
+            canister_id: self.canister_id,
+        }
+    }
+
+    fn principal(&self) -> Principal {
+        self.caller
+    }
+
+    fn time(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.ledger.lock().unwrap().time())
+    }
+
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        Some(self)
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move { self.call(CallKind::Query, method, &arg) })
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move { self.call(CallKind::Update, method, &arg) })
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async move {
+            let ledger = self.ledger.lock().unwrap();
+            Ok((name == "candid:service").then(|| ledger.candid_service().into_bytes()))
+        })
+    }
+}
+
+impl TimeControl for InMemoryLedger {
+    fn set_time(&self, time: SystemTime) {
+        self.ledger.lock().unwrap().set_time(time_nanos(time));
+    }
+
+    fn advance_time(&self, duration: Duration) {
+        let mut ledger = self.ledger.lock().unwrap();
+        let now = ledger.time();
+        ledger.set_time(now + duration.as_nanos() as u64);
+    }
+}
+
+impl InMemoryLedger {
+    /// Creates a ledger with the specified initialization arguments and
+    /// an environment calling it as `caller`.
+    pub fn new(init: InitArgs, caller: Principal) -> Self {
+        Self {
+            counter: Arc::new(AtomicU64::new(0)),
+            ledger: Arc::new(Mutex::new(Ledger::new(init, time_nanos(SystemTime::now())))),
+            caller,
+            canister_id: Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]),
+        }
+    }
+
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    /// Enables the specified defects for all environments sharing this ledger.
+    pub fn set_defects(&self, defects: Defects) {
+        self.ledger.lock().unwrap().set_defects(defects);
+    }
+
+    fn call(&self, kind: CallKind, method: &str, arg: &[u8]) -> Result<Vec<u8>, LedgerCallError> {
+        self.execute(kind, method, arg)
+            .map_err(|(code, msg)| LedgerCallError::Reject {
+                method: method.to_string(),
+                code,
+                message: format!(
+                    "{:?} call to ledger {} failed: {}",
+                    kind, self.canister_id, msg
+                ),
+            })
+    }
+
+    /// Executes a call with Candid-encoded arguments and returns the
+    /// Candid-encoded reply or the reject code and message.
+    fn execute(
+        &self,
+        kind: CallKind,
+        method: &str,
+        arg: &[u8],
+    ) -> Result<Vec<u8>, (RejectCode, String)> {
+        let caller = self.caller;
+        let mut ledger = self.ledger.lock().unwrap();
+
+        match method {
+            "icrc1_transfer" | "icrc2_approve" | "icrc2_transfer_from"
+                if kind == CallKind::Query =>
+            {
+                Err((
+                    RejectCode::DestinationInvalid,
+                    format!("{} is an update method", method),
+                ))
+            }
+            "icrc1_transfer" => reply(arg, |(arg,): (TransferArg,)| {
+                ledger.icrc1_transfer(caller, arg).map(|r| (r,))
+            }),
+            "icrc2_approve" => reply(arg, |(arg,): (ApproveArg,)| {
+                ledger.icrc2_approve(caller, arg).map(|r| (r,))
+            }),
+            "icrc2_transfer_from" => reply(arg, |(arg,): (TransferFromArg,)| {
+                ledger.icrc2_transfer_from(caller, arg).map(|r| (r,))
+            }),
+            "icrc1_balance_of" => reply(arg, |(account,): (Account,)| {
+                Ok((ledger.icrc1_balance_of(account),))
+            }),
+            "icrc1_total_supply" => reply(arg, |()| Ok((ledger.icrc1_total_supply(),))),
+            "icrc1_minting_account" => reply(arg, |()| Ok((ledger.icrc1_minting_account(),))),
+            "icrc1_name" => reply(arg, |()| Ok((ledger.icrc1_name(),))),
+            "icrc1_symbol" => reply(arg, |()| Ok((ledger.icrc1_symbol(),))),
+            "icrc1_decimals" => reply(arg, |()| Ok((ledger.icrc1_decimals(),))),
+            "icrc1_fee" => reply(arg, |()| Ok((ledger.icrc1_fee(),))),
+            "icrc1_metadata" => reply(arg, |()| Ok((ledger.icrc1_metadata(),))),
+            "icrc1_supported_standards" => {
+                reply(arg, |()| Ok((ledger.icrc1_supported_standards(),)))
+            }
+            "icrc2_allowance" => reply(arg, |(arg,): (AllowanceArg,)| {
+                Ok((ledger.icrc2_allowance(arg),))
+            }),
+            _ => Err((
+                RejectCode::DestinationInvalid,
+                format!("the canister has no method {}", method),
+            )),
+        }
+    }
+}
+
+/// Decodes the arguments, executes the method, and encodes the reply.
+/// Errors are reported as canister traps, like the reference ledger does.
+fn reply<A, R>(
+    arg: &[u8],
+    f: impl FnOnce(A) -> Result<R, String>,
+) -> Result<Vec<u8>, (RejectCode, String)>
+where
+    A: for<'a> ArgumentDecoder<'a>,
+    R: ArgumentEncoder,
+{
+    let trap = |msg: String| (RejectCode::CanisterError, msg);
+    let args =
+        decode_args(arg).map_err(|e| trap(format!("failed to decode the arguments: {}", e)))?;
+    encode_args(f(args).map_err(trap)?)
+        .map_err(|e| trap(format!("failed to encode the reply: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use icrc1_test_env::icrc1::{balance_of, total_supply, transfer};
+    use icrc1_test_env::{RecordingLedger, ReplayLedger, Transfer, TransferError};
+
+    fn minter() -> Principal {
+        new_principal(u64::MAX)
+    }
+
+    fn ledger(initial_balance: u64) -> InMemoryLedger {
+        let caller = new_principal(u64::MAX - 1);
+        InMemoryLedger::new(
+            InitArgs {
+                minting_account: Account::from(minter()),
+                initial_mints: vec![(Account::from(caller), Nat::from(initial_balance))],
+                token_name: "Test Token".to_string(),
+                token_symbol: "XTK".to_string(),
+                decimals: 8,
+                transfer_fee: Nat::from(10_000u64),
+            },
+            caller,
+        )
+    }
+
+    #[test]
+    fn test_suite() {
+        let env = ledger(100_000_000_000);
+        futures::executor::block_on(async {
+            let tests = icrc1_test_suite::test_suite(env).await;
+            assert!(!tests.is_empty());
+            assert!(icrc1_test_suite::execute_tests(tests).await);
+        });
+    }
+
+    /// Runs the suite twice with the same forked accounts, like two runs
+    /// with the same identity seed, so the second run finds the funds the
+    /// first run left in the accounts.
+    #[test]
+    fn test_suite_rerun_with_same_accounts() {
+        let env = ledger(100_000_000_000);
+        for _ in 0..2 {
+            let env = InMemoryLedger {
+                counter: Arc::new(AtomicU64::new(0)),
+                ..env.clone()
+            };
+            futures::executor::block_on(async {
+                let tests = icrc1_test_suite::test_suite(env).await;
+                assert!(icrc1_test_suite::execute_tests(tests).await);
+            });
+        }
+    }
+
+    #[test]
+    fn test_suite_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(ledger(100_000_000_000), &path).unwrap();
+        futures::executor::block_on(async {
+            let tests = icrc1_test_suite::test_suite(env.clone()).await;
+            assert!(icrc1_test_suite::execute_tests(tests).await);
+        });
+        env.finish().unwrap();
+
+        let env = ReplayLedger::from_file(&path).unwrap();
+        assert!(env.time_control().is_some());
+        futures::executor::block_on(async {
+            let tests = icrc1_test_suite::test_suite(env.clone()).await;
+            assert!(icrc1_test_suite::execute_tests(tests).await);
+        });
+        assert!(env.unserved().is_empty());
+    }
+
+    /// Checks that each defect makes a specific suite test fail.
+    #[test]
+    fn test_suite_detects_defects() {
+        let cases = vec![
+            (
+                Defects {
+                    skip_deduplication: true,
+                    ..Defects::default()
+                },
+                "icrc1:tx_deduplication",
+            ),
+            (
+                Defects {
+                    wrong_duplicate_of: true,
+                    ..Defects::default()
+                },
+                "icrc1:tx_deduplication",
+            ),
+            (
+                Defects {
+                    charge_wrong_fee: true,
+                    ..Defects::default()
+                },
+                "icrc1:transfer",
+            ),
+            (
+                Defects {
+                    charge_wrong_fee: true,
+                    ..Defects::default()
+                },
+                "icrc1:supply",
+            ),
+            (
+                Defects {
+                    ignore_expires_at: true,
+                    ..Defects::default()
+                },
+                "icrc2:approve_expiration",
+            ),
+            (
+                Defects {
+                    ignore_expires_at: true,
+                    ..Defects::default()
+                },
+                "icrc2:approval_expiry",
+            ),
+            (
+                Defects {
+                    skip_deduplication: true,
+                    ..Defects::default()
+                },
+                "icrc1:tx_window",
+            ),
+            (
+                Defects {
+                    skip_subaccount_normalization: true,
+                    ..Defects::default()
+                },
+                "icrc1:transfer",
+            ),
+            (
+                Defects {
+                    accept_long_memos: true,
+                    ..Defects::default()
+                },
+                "icrc1:oversized_memo",
+            ),
+            (
+                Defects {
+                    ignore_from_subaccount: true,
+                    ..Defects::default()
+                },
+                "icrc1:from_subaccount",
+            ),
+            (
+                Defects {
+                    ignore_from_subaccount: true,
+                    ..Defects::default()
+                },
+                "icrc1:subaccount_isolation",
+            ),
+            (
+                Defects {
+                    balance_of_as_update: true,
+                    ..Defects::default()
+                },
+                "icrc1:candid_interface",
+            ),
+        ];
+
+        for (defects, test_name) in cases {
+            let env = ledger(100_000_000_000);
+            env.set_defects(defects.clone());
+            futures::executor::block_on(async {
+                let test = icrc1_test_suite::test_suite(env)
+                    .await
+                    .into_iter()
+                    .find(|test| test.name() == test_name)
+                    .unwrap_or_else(|| panic!("no test named {}", test_name));
+                assert!(
+                    test.run().await.is_err(),
+                    "{} did not detect {:?}",
+                    test_name,
+                    defects
+                );
+            });
+        }
+    }
+
+    /// Runs the supply test as the minting account, so that it also checks
+    /// mints.
+    #[test]
+    fn test_supply_as_minting_account() {
+        let env = InMemoryLedger::new(
+            InitArgs {
+                minting_account: Account::from(minter()),
+                initial_mints: vec![],
+                token_name: "Test Token".to_string(),
+                token_symbol: "XTK".to_string(),
+                decimals: 8,
+                transfer_fee: Nat::from(10_000u64),
+            },
+            minter(),
+        );
+        futures::executor::block_on(async {
+            assert!(matches!(
+                icrc1_test_suite::icrc1_test_supply(env).await,
+                Ok(icrc1_test_suite::Outcome::Passed)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_fees_are_burned() {
+        let env = ledger(1_000_000);
+        let receiver = env.fork();
+        futures::executor::block_on(async {
+            transfer(&env, Transfer::amount_to(1_000u64, receiver.principal()))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(balance_of(&env, env.principal()).await.unwrap(), 989_000u64);
+            assert_eq!(total_supply(&env).await.unwrap(), 990_000u64);
+        });
+    }
+
+    #[test]
+    fn test_transaction_window() {
+        let env = ledger(1_000_000);
+        let receiver = env.fork();
+        let created_at_time = time_nanos(env.time());
+        let args =
+            Transfer::amount_to(1_000u64, receiver.principal()).created_at_time(created_at_time);
+        futures::executor::block_on(async {
+            transfer(&env, args.clone()).await.unwrap().unwrap();
+            env.advance_time(Duration::from_secs(25 * 60 * 60));
+            assert_eq!(
+                transfer(&env, args).await.unwrap(),
+                Err(TransferError::TooOld)
+            );
+        });
+    }
+
+    #[test]
+    fn test_rejects_update_as_query() {
+        let env = ledger(1_000_000);
+        let receiver = env.fork();
+        futures::executor::block_on(async {
+            let result: Result<(Result<Nat, TransferError>,), _> = env
+                .query(
+                    "icrc1_transfer",
+                    (Transfer::amount_to(1_000u64, receiver.principal()),),
+                )
+                .await;
+            assert!(matches!(
+                result,
+                Err(LedgerCallError::Reject {
+                    code: RejectCode::DestinationInvalid,
+                    ..
+                })
+            ));
+            let result: Result<(Nat,), _> = env.query("icrc1_unknown", ()).await;
+            assert!(matches!(
+                result,
+                Err(LedgerCallError::Reject {
+                    code: RejectCode::DestinationInvalid,
+                    ..
+                })
+            ));
+        });
+    }
+}
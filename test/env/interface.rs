@@ -0,0 +1,261 @@
+//! Checking the Candid interface of a ledger against the interfaces of the
+//! standards.
+
+use candid::types::subtype::{subtype_with_config, Gamma, OptReport};
+use candid::types::{FuncMode, Function, Type, TypeEnv, TypeInner};
+use candid_parser::{check_prog, IDLProg};
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("malformed Candid interface: {message}")]
+pub struct DidParseError {
+    pub message: String,
+}
+
+impl From<candid_parser::Error> for DidParseError {
+    fn from(err: candid_parser::Error) -> Self {
+        Self {
+            message: err.to_string(),
+        }
+    }
+}
+
+/// A method of a service that does not conform to a standard interface.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InterfaceMismatch {
+    #[error("method {method} is missing")]
+    MissingMethod { method: String },
+    #[error("method {method} is annotated as {actual}, the standard requires {expected}")]
+    WrongAnnotation {
+        method: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("the type of method {method} is incompatible with the standard: {message}")]
+    IncompatibleType { method: String, message: String },
+}
+
+/// A service description parsed from a `.did` file.
+#[derive(Clone, Debug)]
+pub struct CandidService {
+    env: TypeEnv,
+    service: Type,
+}
+
+impl CandidService {
+    /// Parses and type checks a service description. Imports are not
+    /// resolved, so the description cannot use types of imported files.
+    pub fn parse(did: &str) -> Result<Self, DidParseError> {
+        let prog: IDLProg = did.parse()?;
+        let mut env = TypeEnv::new();
+        let service = check_prog(&mut env, &prog)?.ok_or_else(|| DidParseError {
+            message: "the interface does not describe a service".to_string(),
+        })?;
+        // Only the methods matter, not the arguments of a service class.
+        let service = match service.as_ref() {
+            TypeInner::Class(_, service) => service.clone(),
+            _ => service,
+        };
+        Ok(Self { env, service })
+    }
+
+    /// Returns the names of the methods of the service.
+    pub fn methods(&self) -> Vec<String> {
+        self.env
+            .as_service(&self.service)
+            .map(|methods| methods.iter().map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the methods of this service that do not conform to the
+    /// `standard` service. A method conforms if it has the same annotation
+    /// as the standard method and its type is a subtype of the standard
+    /// type, without resorting to the special `opt` rule.
+    pub fn mismatches(&self, standard: &CandidService) -> Vec<InterfaceMismatch> {
+        let mut env = self.env.clone();
+        let standard_service = env.merge_type(standard.env.clone(), standard.service.clone());
+        let mut mismatches = vec![];
+
+        for method in standard.methods() {
+            let expected = env
+                .get_method(&standard_service, &method)
+                .expect("validated when parsing");
+            let actual = match env.get_method(&self.service, &method) {
+                Ok(actual) => actual,
+                Err(_) => {
+                    mismatches.push(InterfaceMismatch::MissingMethod { method });
+                    continue;
+                }
+            };
+
+            if actual.modes != expected.modes {
+                mismatches.push(InterfaceMismatch::WrongAnnotation {
+                    method: method.clone(),
+                    expected: annotation(&expected.modes),
+                    actual: annotation(&actual.modes),
+                });
+            }
+
+            // The annotations are reported above, compare the types only.
+            let actual = Function {
+                modes: expected.modes.clone(),
+                ..actual.clone()
+            };
+            if let Err(err) = subtype_with_config(
+                OptReport::Error,
+                &mut Gamma::new(),
+                &env,
+                &TypeInner::Func(actual).into(),
+                &TypeInner::Func(expected.clone()).into(),
+            ) {
+                mismatches.push(InterfaceMismatch::IncompatibleType {
+                    method,
+                    message: format!("{:#}", err),
+                });
+            }
+        }
+        mismatches
+    }
+}
+
+fn annotation(modes: &[FuncMode]) -> String {
+    if modes.is_empty() {
+        return "update".to_string();
+    }
+    modes
+        .iter()
+        .map(|mode| match mode {
+            FuncMode::Query => "query",
+            FuncMode::CompositeQuery => "composite_query",
+            FuncMode::Oneway => "oneway",
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STANDARD: &str = r#"
+        // A comment.
+        type Account = record { owner : principal; subaccount : opt blob };
+        type Value = variant { Nat : nat; Text : text; Unit };
+        service : {
+            balance_of : (Account) -> (nat) query;
+            transfer : (record { to : Account; amount : nat }) -> (variant { Ok : nat; Err : text });
+            metadata : () -> (vec record { text; Value }) query;
+        }
+    "#;
+
+    fn mismatches(actual: &str) -> Vec<InterfaceMismatch> {
+        CandidService::parse(actual)
+            .unwrap()
+            .mismatches(&CandidService::parse(STANDARD).unwrap())
+    }
+
+    #[test]
+    fn test_conforming_interface() {
+        assert_eq!(mismatches(STANDARD), vec![]);
+        // Extra methods, extra optional arguments, extra result fields, and
+        // fewer variant cases in results are fine. The type names may differ
+        // and clash with unrelated standard types.
+        let actual = r#"
+            type Account = text;
+            type Acc = record { owner : principal; subaccount : opt vec nat8 };
+            type Args = record { to : Acc; amount : nat; memo : opt blob };
+            service : (nat) -> {
+                "balance_of" : (Acc) -> (nat) query;
+                transfer : (args : Args) -> (variant { Ok : nat }, opt nat);
+                metadata : () -> (vec record { 0 : text; 1 : variant { Nat : nat } }) query;
+                name : () -> (Account) query;
+            }
+        "#;
+        assert_eq!(mismatches(actual), vec![]);
+    }
+
+    #[test]
+    fn test_reports_mismatches() {
+        let actual = r#"
+            type Account = record { owner : principal; subaccount : opt blob };
+            service : {
+                balance_of : (Account) -> (nat);
+                transfer : (record { to : Account; amount : nat64 }) -> (variant { Ok : nat; Err : text });
+            }
+        "#;
+        let mismatches = mismatches(actual);
+        assert_eq!(mismatches.len(), 3, "{:?}", mismatches);
+        assert_eq!(
+            mismatches[0],
+            InterfaceMismatch::WrongAnnotation {
+                method: "balance_of".to_string(),
+                expected: "query".to_string(),
+                actual: "update".to_string(),
+            }
+        );
+        assert_eq!(
+            mismatches[1],
+            InterfaceMismatch::MissingMethod {
+                method: "metadata".to_string()
+            }
+        );
+        assert!(
+            matches!(&mismatches[2], InterfaceMismatch::IncompatibleType { method, .. } if method == "transfer")
+        );
+    }
+
+    #[test]
+    fn test_malformed_interfaces() {
+        for did in [
+            "type A = record {\n nat;\n : nat };\nservice : {}",
+            "service : { m : (Undefined) -> () }",
+            "type A = B;\ntype B = A;\nservice : {}",
+            "type A = nat;",
+            "service : { m : () -> (); m : () -> () }",
+        ]
+        .iter()
+        {
+            assert!(CandidService::parse(did).is_err(), "{}", did);
+        }
+    }
+
+    /// Uses the constructs of the standard interfaces: comments, recursive
+    /// types, function references, and tuple records.
+    const LEDGER: &str = r#"
+        // Number of nanoseconds since the UNIX epoch in UTC timezone.
+        type Timestamp = nat64;
+        type Account = record { owner : principal; subaccount : opt blob };
+        type Value = variant {
+            Nat : nat;
+            Text : text;
+            Array : vec Value;
+            Map : vec record { text; Value };
+        };
+        type GetBlocksArgs = vec record { start : nat; length : nat };
+        type GetBlocksResult = record {
+            log_length : nat;
+            blocks : vec record { id : nat; block : Value };
+            archived_blocks : vec record {
+                args : GetBlocksArgs;
+                callback : func (GetBlocksArgs) -> (GetBlocksResult) query;
+            };
+        };
+        service : {
+            icrc1_metadata : () -> (vec record { text; Value; }) query;
+            icrc1_transfer : (record { to : Account; created_at_time : opt Timestamp }) -> (
+                variant { Ok : nat; Err : variant { TooOld; CreatedInFuture : record { ledger_time : Timestamp } } },
+            );
+            icrc3_get_blocks : (GetBlocksArgs) -> (GetBlocksResult) query;
+        }
+    "#;
+
+    #[test]
+    fn test_ledger_interface_parses() {
+        let service = CandidService::parse(LEDGER).unwrap();
+        assert_eq!(
+            service.methods(),
+            vec!["icrc1_metadata", "icrc1_transfer", "icrc3_get_blocks"]
+        );
+        assert_eq!(service.mismatches(&service), vec![]);
+    }
+}
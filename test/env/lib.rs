@@ -1,10 +1,51 @@
-use async_trait::async_trait;
 use candid::utils::{ArgumentDecoder, ArgumentEncoder};
 use candid::Principal;
-use candid::{CandidType, Int, Nat};
+use candid::{CandidType, Nat};
 use serde::Deserialize;
 use thiserror::Error;
 
+mod account;
+mod amount;
+mod block;
+mod block_stream;
+mod certificate;
+mod chain;
+mod error;
+mod fault;
+pub mod hash;
+mod interface;
+pub mod metadata;
+mod record;
+mod seed;
+mod send;
+mod supply;
+mod test_account;
+mod time;
+mod trace;
+mod value;
+
+pub use account::AccountParseError;
+pub use amount::{AmountError, AmountParseError, TokenAmount};
+pub use block::{Block, BlockDecodeError, BlockMeta};
+pub use block_stream::{sibling_env, BlockStream, BlockStreamError};
+pub use certificate::{tip_from_hash_tree, verify_tip_certificate, CertificateError};
+pub use chain::{verify_chain, ChainError, ChainVerifier, Tip};
+pub use error::{decode_call_reply, encode_call_args, LedgerCallError, RejectCode};
+pub use fault::{Fault, FaultSchedule, FaultyLedger, InjectedFault};
+pub use hash::hash_value;
+pub use interface::{CandidService, DidParseError, InterfaceMismatch};
+pub use metadata::{MetadataError, TokenMetadata, TokenMetadataError};
+pub use record::{
+    CallKind, RecordedCall, RecordedEvent, RecordingLedger, ReplayError, ReplayLedger,
+};
+pub use seed::IdentitySeed;
+pub use send::{LedgerFuture, MaybeSend, MaybeSync};
+pub use supply::{with_supply_snapshots, SupplyChange, SupplySnapshot};
+pub use test_account::{fund_subaccounts, named_subaccount, TestAccount};
+pub use time::TimeControl;
+pub use trace::{LatencyHistogram, MethodMetrics, TracingLedger};
+pub use value::Value;
+
 pub type Subaccount = [u8; 32];
 
 #[derive(CandidType, Clone, Debug, Deserialize, Eq, PartialEq)]
@@ -28,14 +69,6 @@ pub struct SupportedStandard {
     pub url: String,
 }
 
-#[derive(CandidType, Clone, Debug, Deserialize, PartialEq)]
-pub enum Value {
-    Text(String),
-    Blob(Vec<u8>),
-    Nat(Nat),
-    Int(Int),
-}
-
 #[derive(CandidType, Deserialize, PartialEq, Eq, Debug, Clone, Error)]
 pub enum TransferError {
     #[error("Invalid transfer fee, the ledger expected fee {expected_fee}")]
@@ -257,45 +290,212 @@ pub struct Allowance {
     pub expires_at: Option<u64>,
 }
 
-#[async_trait(?Send)]
-pub trait LedgerEnv {
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetArchivesArgs {
+    /// The last archive seen by the client.
+    pub from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub canister_id: Principal,
+    /// The first block in the archive.
+    pub start: Nat,
+    /// The last block in the archive.
+    pub end: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetBlocksRequest {
+    pub start: Nat,
+    pub length: Nat,
+}
+
+impl GetBlocksRequest {
+    pub fn new(start: impl Into<Nat>, length: impl Into<Nat>) -> Self {
+        Self {
+            start: start.into(),
+            length: length.into(),
+        }
+    }
+}
+
+pub type GetBlocksArgs = Vec<GetBlocksRequest>;
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockWithId {
+    pub id: Nat,
+    pub block: Value,
+}
+
+candid::define_function!(pub GetBlocksFn : (GetBlocksArgs) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedBlocks {
+    pub args: GetBlocksArgs,
+    pub callback: GetBlocksFn,
+}
+
+impl ArchivedBlocks {
+    /// Returns the id of the canister holding the archived blocks.
+    pub fn canister_id(&self) -> Principal {
+        self.callback.0.principal
+    }
+
+    /// Returns the name of the query method serving the archived blocks.
+    pub fn method(&self) -> &str {
+        &self.callback.0.method
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetBlocksResult {
+    /// Total number of blocks in the block log.
+    pub log_length: Nat,
+    /// Blocks found locally to the ledger.
+    pub blocks: Vec<BlockWithId>,
+    /// Callbacks to fetch the blocks that are not local to the ledger.
+    pub archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DataCertificate {
+    /// See https://internetcomputer.org/docs/current/references/ic-interface-spec#certification
+    pub certificate: Vec<u8>,
+    /// CBOR-encoded hash tree.
+    pub hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SupportedBlockType {
+    pub block_type: String,
+    pub url: String,
+}
+
+/// An environment for calling a ledger as a specific caller.
+///
+/// With the `send` feature, environments and the futures they return are
+/// `Send` and `Sync`.
+pub trait LedgerEnv: MaybeSend + MaybeSync {
     /// Creates a new environment pointing to the same ledger but using a new caller.
     fn fork(&self) -> Self;
 
+    /// Returns an environment with the same caller whose forks derive their
+    /// identities from the specified label, like a test name, see
+    /// [IdentitySeed::derive]. Returns `None` if the environment does not
+    /// derive identities from a seed.
+    fn with_label(&self, _label: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Returns an environment with the same caller that calls the canister
+    /// with the specified id instead, for example an archive of the ledger
+    /// or a second ledger. Forks of the returned environment call the same
+    /// canister. Returns `None` if the environment cannot call other
+    /// canisters.
+    fn with_canister(&self, _canister_id: Principal) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
     /// Returns the caller's principal.
     fn principal(&self) -> Principal;
 
     /// Returns the approximation of the current ledger time.
     fn time(&self) -> std::time::SystemTime;
 
+    /// Returns the control of the ledger time, if the environment can move it.
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        None
+    }
+
+    /// Executes a query call with Candid-encoded arguments on the ledger and
+    /// returns the Candid-encoded reply.
+    ///
+    /// The arguments are sent as is, which allows checking how the ledger
+    /// handles malformed input.
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>>;
+
+    /// Executes an update call with Candid-encoded arguments on the ledger and
+    /// returns the Candid-encoded reply.
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>>;
+
+    /// Reads the canister metadata section with the specified name, like
+    /// `candid:service`. Returns `None` if the canister has no such section
+    /// or the environment cannot read canister metadata.
+    fn canister_metadata<'a>(
+        &'a self,
+        _name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async { Ok(None) })
+    }
+
     /// Executes a query call with the specified arguments on the ledger.
-    async fn query<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
+    fn query<'a, Input, Output>(
+        &'a self,
+        method: &'a str,
+        input: Input,
+    ) -> LedgerFuture<'a, Result<Output, LedgerCallError>>
     where
         Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>;
+        Output: for<'b> ArgumentDecoder<'b> + 'a,
+    {
+        let arg = encode_call_args(method, input);
+        Box::pin(async move {
+            let reply = self.query_raw(method, arg?).await?;
+            decode_call_reply(method, &reply)
+        })
+    }
 
     /// Executes an update call with the specified arguments on the ledger.
-    async fn update<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
+    fn update<'a, Input, Output>(
+        &'a self,
+        method: &'a str,
+        input: Input,
+    ) -> LedgerFuture<'a, Result<Output, LedgerCallError>>
     where
         Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>;
+        Output: for<'b> ArgumentDecoder<'b> + 'a,
+    {
+        let arg = encode_call_args(method, input);
+        Box::pin(async move {
+            let reply = self.update_raw(method, arg?).await?;
+            decode_call_reply(method, &reply)
+        })
+    }
 }
 
 pub mod icrc1 {
-    use crate::{Account, LedgerEnv, SupportedStandard, Transfer, TransferError, Value};
+    use crate::{
+        Account, LedgerCallError, LedgerEnv, SupportedStandard, TokenMetadata, TokenMetadataError,
+        Transfer, TransferError, Value,
+    };
     use candid::Nat;
 
     pub async fn transfer(
         ledger: &impl LedgerEnv,
         arg: Transfer,
-    ) -> anyhow::Result<Result<Nat, TransferError>> {
+    ) -> Result<Result<Nat, TransferError>, LedgerCallError> {
         ledger.update("icrc1_transfer", (arg,)).await.map(|(t,)| t)
     }
 
     pub async fn balance_of(
         ledger: &impl LedgerEnv,
         account: impl Into<Account>,
-    ) -> anyhow::Result<Nat> {
+    ) -> Result<Nat, LedgerCallError> {
         ledger
             .query("icrc1_balance_of", (account.into(),))
             .await
@@ -304,59 +504,73 @@ pub mod icrc1 {
 
     pub async fn supported_standards(
         ledger: &impl LedgerEnv,
-    ) -> anyhow::Result<Vec<SupportedStandard>> {
+    ) -> Result<Vec<SupportedStandard>, LedgerCallError> {
         ledger
             .query("icrc1_supported_standards", ())
             .await
             .map(|(t,)| t)
     }
 
-    pub async fn metadata(ledger: &impl LedgerEnv) -> anyhow::Result<Vec<(String, Value)>> {
+    pub async fn metadata(
+        ledger: &impl LedgerEnv,
+    ) -> Result<Vec<(String, Value)>, LedgerCallError> {
         ledger.query("icrc1_metadata", ()).await.map(|(t,)| t)
     }
 
-    pub async fn minting_account(ledger: &impl LedgerEnv) -> anyhow::Result<Option<Account>> {
+    pub async fn token_metadata(
+        ledger: &impl LedgerEnv,
+    ) -> Result<TokenMetadata, TokenMetadataError> {
+        Ok(TokenMetadata::from_entries(&metadata(ledger).await?)?)
+    }
+
+    pub async fn minting_account(
+        ledger: &impl LedgerEnv,
+    ) -> Result<Option<Account>, LedgerCallError> {
         ledger
             .query("icrc1_minting_account", ())
             .await
             .map(|(t,)| t)
     }
 
-    pub async fn token_name(ledger: &impl LedgerEnv) -> anyhow::Result<String> {
+    pub async fn token_name(ledger: &impl LedgerEnv) -> Result<String, LedgerCallError> {
         ledger.query("icrc1_name", ()).await.map(|(t,)| t)
     }
 
-    pub async fn token_symbol(ledger: &impl LedgerEnv) -> anyhow::Result<String> {
+    pub async fn token_symbol(ledger: &impl LedgerEnv) -> Result<String, LedgerCallError> {
         ledger.query("icrc1_symbol", ()).await.map(|(t,)| t)
     }
 
-    pub async fn token_decimals(ledger: &impl LedgerEnv) -> anyhow::Result<u8> {
+    pub async fn token_decimals(ledger: &impl LedgerEnv) -> Result<u8, LedgerCallError> {
         ledger.query("icrc1_decimals", ()).await.map(|(t,)| t)
     }
 
-    pub async fn transfer_fee(ledger: &impl LedgerEnv) -> anyhow::Result<Nat> {
+    pub async fn transfer_fee(ledger: &impl LedgerEnv) -> Result<Nat, LedgerCallError> {
         ledger.query("icrc1_fee", ()).await.map(|(t,)| t)
     }
+
+    pub async fn total_supply(ledger: &impl LedgerEnv) -> Result<Nat, LedgerCallError> {
+        ledger.query("icrc1_total_supply", ()).await.map(|(t,)| t)
+    }
 }
 
 pub mod icrc2 {
     use crate::{
-        Allowance, AllowanceArgs, ApproveArgs, ApproveError, LedgerEnv, TransferFromArgs,
-        TransferFromError,
+        Allowance, AllowanceArgs, ApproveArgs, ApproveError, LedgerCallError, LedgerEnv,
+        TransferFromArgs, TransferFromError,
     };
     use candid::Nat;
 
     pub async fn approve(
         ledger: &impl LedgerEnv,
         arg: ApproveArgs,
-    ) -> anyhow::Result<Result<Nat, ApproveError>> {
+    ) -> Result<Result<Nat, ApproveError>, LedgerCallError> {
         ledger.update("icrc2_approve", (arg,)).await.map(|(t,)| t)
     }
 
     pub async fn transfer_from(
         ledger: &impl LedgerEnv,
         arg: TransferFromArgs,
-    ) -> anyhow::Result<Result<Nat, TransferFromError>> {
+    ) -> Result<Result<Nat, TransferFromError>, LedgerCallError> {
         ledger
             .update("icrc2_transfer_from", (arg,))
             .await
@@ -366,7 +580,92 @@ pub mod icrc2 {
     pub async fn allowance(
         ledger: &impl LedgerEnv,
         arg: AllowanceArgs,
-    ) -> anyhow::Result<Allowance> {
+    ) -> Result<Allowance, LedgerCallError> {
         ledger.query("icrc2_allowance", (arg,)).await.map(|(t,)| t)
     }
 }
+
+pub mod icrc3 {
+    use crate::{
+        ArchiveInfo, ArchivedBlocks, DataCertificate, GetArchivesArgs, GetBlocksArgs,
+        GetBlocksResult, LedgerCallError, LedgerEnv, SupportedBlockType,
+    };
+
+    pub async fn get_blocks(
+        ledger: &impl LedgerEnv,
+        args: GetBlocksArgs,
+    ) -> Result<GetBlocksResult, LedgerCallError> {
+        ledger
+            .query("icrc3_get_blocks", (args,))
+            .await
+            .map(|(t,)| t)
+    }
+
+    /// Fetches the blocks described by an `archived_blocks` entry of
+    /// [GetBlocksResult] from the `archive` environment, which must point to
+    /// the canister returned by [ArchivedBlocks::canister_id].
+    pub async fn get_archived_blocks(
+        archive: &impl LedgerEnv,
+        archived: &ArchivedBlocks,
+    ) -> Result<GetBlocksResult, LedgerCallError> {
+        archive
+            .query(archived.method(), (archived.args.clone(),))
+            .await
+            .map(|(t,)| t)
+    }
+
+    pub async fn get_archives(
+        ledger: &impl LedgerEnv,
+        arg: GetArchivesArgs,
+    ) -> Result<Vec<ArchiveInfo>, LedgerCallError> {
+        ledger
+            .query("icrc3_get_archives", (arg,))
+            .await
+            .map(|(t,)| t)
+    }
+
+    pub async fn get_tip_certificate(
+        ledger: &impl LedgerEnv,
+    ) -> Result<Option<DataCertificate>, LedgerCallError> {
+        ledger
+            .query("icrc3_get_tip_certificate", ())
+            .await
+            .map(|(t,)| t)
+    }
+
+    pub async fn supported_block_types(
+        ledger: &impl LedgerEnv,
+    ) -> Result<Vec<SupportedBlockType>, LedgerCallError> {
+        ledger
+            .query("icrc3_supported_block_types", ())
+            .await
+            .map(|(t,)| t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::{Decode, Encode};
+
+    #[test]
+    fn test_archived_blocks_roundtrip() {
+        let archive = Principal::from_text("qjdve-lqaaa-aaaaa-aaaeq-cai").unwrap();
+        let result = GetBlocksResult {
+            log_length: Nat::from(10u8),
+            blocks: vec![BlockWithId {
+                id: Nat::from(9u8),
+                block: Value::Map(vec![("ts".to_string(), Value::Nat(Nat::from(1u8)))]),
+            }],
+            archived_blocks: vec![ArchivedBlocks {
+                args: vec![GetBlocksRequest::new(0u8, 9u8)],
+                callback: GetBlocksFn::new(archive, "icrc3_get_blocks".to_string()),
+            }],
+        };
+        let bytes = Encode!(&result).unwrap();
+        let decoded = Decode!(&bytes, GetBlocksResult).unwrap();
+        assert_eq!(decoded, result);
+        assert_eq!(decoded.archived_blocks[0].canister_id(), archive);
+        assert_eq!(decoded.archived_blocks[0].method(), "icrc3_get_blocks");
+    }
+}
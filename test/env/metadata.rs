@@ -0,0 +1,271 @@
+//! Typed view of the ICRC-1 metadata entries.
+//!
+//! See the "Metadata" section of the ICRC-1 standard.
+
+use crate::{LedgerCallError, Value};
+use candid::Nat;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+pub const NAME_KEY: &str = "icrc1:name";
+pub const SYMBOL_KEY: &str = "icrc1:symbol";
+pub const DECIMALS_KEY: &str = "icrc1:decimals";
+pub const FEE_KEY: &str = "icrc1:fee";
+pub const LOGO_KEY: &str = "icrc1:logo";
+pub const MAX_MEMO_LENGTH_KEY: &str = "icrc1:max_memo_length";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MetadataError {
+    #[error("metadata key {0:?} does not follow the <namespace>:<key> format")]
+    MalformedKey(String),
+    #[error("metadata key {0} is duplicated")]
+    DuplicateKey(String),
+    #[error("metadata entry {key} must be a {expected}, got {actual:?}")]
+    WrongType {
+        key: String,
+        expected: &'static str,
+        actual: Value,
+    },
+    #[error("metadata entry {key} is out of range: {value}")]
+    OutOfRange { key: String, value: Nat },
+}
+
+/// The error of [crate::icrc1::token_metadata].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TokenMetadataError {
+    /// The `icrc1_metadata` call failed.
+    #[error(transparent)]
+    Call(#[from] LedgerCallError),
+    /// The ledger returned metadata violating the standard.
+    #[error(transparent)]
+    InvalidMetadata(#[from] MetadataError),
+}
+
+/// The metadata of a token, as returned by `icrc1_metadata`.
+///
+/// Standard entries are parsed into typed fields; all other entries are
+/// kept in [TokenMetadata::extensions] grouped by namespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    pub fee: Option<Nat>,
+    pub logo: Option<String>,
+    pub max_memo_length: Option<u64>,
+    /// Non-standard entries, indexed by namespace and then by the key
+    /// without the namespace prefix.
+    pub extensions: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+impl TokenMetadata {
+    /// Parses metadata entries, checking the key format and the types
+    /// of the standard entries.
+    pub fn from_entries(entries: &[(String, Value)]) -> Result<Self, MetadataError> {
+        let mut metadata = Self::default();
+        let mut seen = BTreeSet::new();
+
+        for (key, value) in entries {
+            let (namespace, name) = split_key(key)?;
+            if !seen.insert(key.as_str()) {
+                return Err(MetadataError::DuplicateKey(key.clone()));
+            }
+            match key.as_str() {
+                NAME_KEY => metadata.name = Some(text(key, value)?),
+                SYMBOL_KEY => metadata.symbol = Some(text(key, value)?),
+                DECIMALS_KEY => metadata.decimals = Some(nat_as(key, value)?),
+                FEE_KEY => metadata.fee = Some(nat(key, value)?.clone()),
+                LOGO_KEY => metadata.logo = Some(text(key, value)?),
+                MAX_MEMO_LENGTH_KEY => metadata.max_memo_length = Some(nat_as(key, value)?),
+                _ => {
+                    metadata
+                        .extensions
+                        .entry(namespace.to_string())
+                        .or_default()
+                        .insert(name.to_string(), value.clone());
+                }
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Converts the metadata back into `icrc1_metadata` entries.
+    pub fn to_entries(&self) -> Vec<(String, Value)> {
+        let mut entries = vec![];
+        let mut push = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                entries.push((key.to_string(), value));
+            }
+        };
+        push(NAME_KEY, self.name.clone().map(Value::Text));
+        push(SYMBOL_KEY, self.symbol.clone().map(Value::Text));
+        push(
+            DECIMALS_KEY,
+            self.decimals.map(|d| Value::Nat(Nat::from(d))),
+        );
+        push(FEE_KEY, self.fee.clone().map(Value::Nat));
+        push(LOGO_KEY, self.logo.clone().map(Value::Text));
+        push(
+            MAX_MEMO_LENGTH_KEY,
+            self.max_memo_length.map(|l| Value::Nat(Nat::from(l))),
+        );
+        for (namespace, values) in self.extensions.iter() {
+            for (name, value) in values.iter() {
+                entries.push((format!("{}:{}", namespace, name), value.clone()));
+            }
+        }
+        entries
+    }
+
+    /// Returns all non-standard entries of the specified namespace.
+    pub fn namespace(&self, namespace: &str) -> Option<&BTreeMap<String, Value>> {
+        self.extensions.get(namespace)
+    }
+
+    /// Looks up a non-standard entry by its full key, e.g. `"icrc2:fee"`.
+    pub fn extension(&self, key: &str) -> Option<&Value> {
+        let (namespace, name) = split_key(key).ok()?;
+        self.extensions.get(namespace)?.get(name)
+    }
+}
+
+impl TryFrom<Vec<(String, Value)>> for TokenMetadata {
+    type Error = MetadataError;
+
+    fn try_from(entries: Vec<(String, Value)>) -> Result<Self, Self::Error> {
+        Self::from_entries(&entries)
+    }
+}
+
+fn split_key(key: &str) -> Result<(&str, &str), MetadataError> {
+    match key.split_once(':') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => {
+            Ok((namespace, name))
+        }
+        _ => Err(MetadataError::MalformedKey(key.to_string())),
+    }
+}
+
+fn wrong_type(key: &str, expected: &'static str, actual: &Value) -> MetadataError {
+    MetadataError::WrongType {
+        key: key.to_string(),
+        expected,
+        actual: actual.clone(),
+    }
+}
+
+fn text(key: &str, value: &Value) -> Result<String, MetadataError> {
+    value
+        .as_text()
+        .map(str::to_string)
+        .ok_or_else(|| wrong_type(key, "Text", value))
+}
+
+fn nat<'a>(key: &str, value: &'a Value) -> Result<&'a Nat, MetadataError> {
+    value.as_nat().ok_or_else(|| wrong_type(key, "Nat", value))
+}
+
+fn nat_as<T: TryFrom<u64>>(key: &str, value: &Value) -> Result<T, MetadataError> {
+    let n = nat(key, value)?;
+    u64::try_from(&n.0)
+        .ok()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| MetadataError::OutOfRange {
+            key: key.to_string(),
+            value: n.clone(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<(String, Value)> {
+        vec![
+            (NAME_KEY.to_string(), Value::from("Test Token")),
+            (SYMBOL_KEY.to_string(), Value::from("XTKN")),
+            (DECIMALS_KEY.to_string(), Value::Nat(Nat::from(8u8))),
+            (FEE_KEY.to_string(), Value::Nat(Nat::from(10_000u64))),
+            (MAX_MEMO_LENGTH_KEY.to_string(), Value::Nat(Nat::from(32u8))),
+            ("icrc2:fee".to_string(), Value::Nat(Nat::from(5u8))),
+            ("icrc1:tags".to_string(), Value::from("defi")),
+            ("dex:pair:XTKN".to_string(), Value::from("ICP")),
+        ]
+    }
+
+    #[test]
+    fn test_standard_fields() {
+        let metadata = TokenMetadata::from_entries(&entries()).unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("Test Token"));
+        assert_eq!(metadata.symbol.as_deref(), Some("XTKN"));
+        assert_eq!(metadata.decimals, Some(8));
+        assert_eq!(metadata.fee, Some(Nat::from(10_000u64)));
+        assert_eq!(metadata.logo, None);
+        assert_eq!(metadata.max_memo_length, Some(32));
+    }
+
+    #[test]
+    fn test_extensions() {
+        let metadata = TokenMetadata::from_entries(&entries()).unwrap();
+        assert_eq!(
+            metadata.extension("icrc2:fee"),
+            Some(&Value::Nat(Nat::from(5u8)))
+        );
+        assert_eq!(metadata.extension("icrc1:tags"), Some(&Value::from("defi")));
+        assert_eq!(
+            metadata.namespace("dex").unwrap().get("pair:XTKN"),
+            Some(&Value::from("ICP"))
+        );
+        assert_eq!(metadata.extension("icrc2:name"), None);
+
+        let mut roundtrip = metadata.to_entries();
+        let mut expected = entries();
+        roundtrip.sort_by(|l, r| l.0.cmp(&r.0));
+        expected.sort_by(|l, r| l.0.cmp(&r.0));
+        assert_eq!(roundtrip, expected);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let parse = |key: &str, value: Value| {
+            TokenMetadata::from_entries(&[(key.to_string(), value)]).unwrap_err()
+        };
+        assert_eq!(
+            parse("name", Value::from("Test Token")),
+            MetadataError::MalformedKey("name".to_string())
+        );
+        assert_eq!(
+            parse(":name", Value::from("Test Token")),
+            MetadataError::MalformedKey(":name".to_string())
+        );
+        assert!(matches!(
+            parse(NAME_KEY, Value::Nat(Nat::from(1u8))),
+            MetadataError::WrongType {
+                expected: "Text",
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse(FEE_KEY, Value::from("10000")),
+            MetadataError::WrongType {
+                expected: "Nat",
+                ..
+            }
+        ));
+        assert_eq!(
+            parse(DECIMALS_KEY, Value::Nat(Nat::from(256u16))),
+            MetadataError::OutOfRange {
+                key: DECIMALS_KEY.to_string(),
+                value: Nat::from(256u16),
+            }
+        );
+
+        let mut duplicated = entries();
+        duplicated.push((SYMBOL_KEY.to_string(), Value::from("XTKN")));
+        assert_eq!(
+            TokenMetadata::from_entries(&duplicated).unwrap_err(),
+            MetadataError::DuplicateKey(SYMBOL_KEY.to_string())
+        );
+    }
+}
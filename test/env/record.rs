@@ -0,0 +1,894 @@
+//! Recording ledger calls and replaying them without a ledger.
+
+use crate::{LedgerCallError, LedgerEnv, LedgerFuture, TimeControl};
+use candid::Principal;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallKind {
+    Query,
+    Update,
+}
+
+/// A single call recorded by a [RecordingLedger].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub kind: CallKind,
+    pub method: String,
+    pub caller: Principal,
+    /// The called canister if it is not the ledger, see
+    /// [LedgerEnv::with_canister].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canister: Option<Principal>,
+    /// The ledger time after the call, in nanoseconds since the UNIX epoch.
+    pub time: u64,
+    /// The hex-encoded Candid arguments.
+    pub arg: String,
+    /// The hex-encoded Candid reply or the error.
+    pub reply: Result<String, LedgerCallError>,
+}
+
+/// An entry of a recording, stored as a single JSON line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    /// An environment was created. The one without label and fork index
+    /// is the original environment.
+    Env {
+        principal: Principal,
+        /// The label of the environment, see [LedgerEnv::with_label].
+        /// Forks have the label of the environment they were forked from.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        /// The index of the fork among the forks with the same label.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fork: Option<u64>,
+        /// Whether the environment could move the ledger time, see
+        /// [LedgerEnv::time_control].
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        time_control: bool,
+    },
+    /// The ledger time was read by the environment with the same label and
+    /// fork index.
+    Time {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fork: Option<u64>,
+        nanos: u64,
+    },
+    Call(RecordedCall),
+    /// A canister metadata section was read.
+    Metadata {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        canister: Option<Principal>,
+        name: String,
+        /// The hex-encoded section, `None` if the canister has none with
+        /// this name, or the error.
+        reply: Result<Option<String>, LedgerCallError>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("failed to read the recording: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed recording entry on line {line}: {message}")]
+    Malformed { line: usize, message: String },
+    #[error("the recording does not contain any environment")]
+    NoEnv,
+}
+
+fn time_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("the time is before the UNIX epoch")
+        .as_nanos() as u64
+}
+
+/// Identifies an environment across a recording and its replay by the
+/// label and the fork index, which do not depend on the order in which
+/// concurrently running tests create environments.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct EnvKey {
+    label: Option<String>,
+    fork: Option<u64>,
+}
+
+/// Hands out the fork indices of the environments with the given label.
+#[derive(Default)]
+struct ForkCounter {
+    next: HashMap<Option<String>, u64>,
+}
+
+impl ForkCounter {
+    fn fork(&mut self, parent: &EnvKey) -> EnvKey {
+        let next = self.next.entry(parent.label.clone()).or_default();
+        let fork = *next;
+        *next += 1;
+        EnvKey {
+            label: parent.label.clone(),
+            fork: Some(fork),
+        }
+    }
+}
+
+struct Recorder {
+    file: File,
+    forks: ForkCounter,
+    /// The first error writing the recording. Nothing is written after it.
+    error: Option<std::io::Error>,
+}
+
+impl Recorder {
+    fn log(&mut self, event: &RecordedEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = serde_json::to_string(event)
+            .map_err(std::io::Error::from)
+            .and_then(|mut line| {
+                line.push('\n');
+                self.file.write_all(line.as_bytes())
+            });
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+}
+
+/// A `LedgerEnv` decorator writing every call of the wrapped environment
+/// to a file that a [ReplayLedger] can serve without a ledger.
+///
+/// The recording contains the caller, the method, the Candid-encoded
+/// arguments and reply, and the ledger time of each call, as well as the
+/// principals of labeled and forked environments and the ledger time reads.
+/// Labeling an environment always succeeds, the labeled environment uses the
+/// wrapped one if that does not support labels.
+///
+/// Failing to write the recording does not fail the calls, call
+/// [RecordingLedger::finish] to check that the recording is complete.
+#[derive(Clone)]
+pub struct RecordingLedger<L> {
+    inner: L,
+    recorder: Arc<Mutex<Recorder>>,
+    env: EnvKey,
+    canister: Option<Principal>,
+}
+
+impl<L: LedgerEnv> RecordingLedger<L> {
+    /// Creates the recording file, overwriting an existing one.
+    pub fn new(inner: L, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut recorder = Recorder {
+            file: File::create(path)?,
+            forks: ForkCounter::default(),
+            error: None,
+        };
+        recorder.log(&RecordedEvent::Env {
+            principal: inner.principal(),
+            label: None,
+            fork: None,
+            time_control: inner.time_control().is_some(),
+        });
+        if let Some(err) = recorder.error.take() {
+            return Err(err);
+        }
+        Ok(Self {
+            inner,
+            recorder: Arc::new(Mutex::new(recorder)),
+            env: EnvKey::default(),
+            canister: None,
+        })
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Flushes the recording and returns the first error writing it, so
+    /// that a run is not mistaken for a complete recording. The
+    /// environments forked from this one share the recording, the error is
+    /// returned only once.
+    pub fn finish(&self) -> std::io::Result<()> {
+        let mut recorder = self.recorder.lock().unwrap();
+        match recorder.error.take() {
+            Some(err) => Err(err),
+            None => recorder.file.flush(),
+        }
+    }
+
+    fn log(&self, event: &RecordedEvent) {
+        self.recorder.lock().unwrap().log(event)
+    }
+
+    fn log_env(recorder: &mut Recorder, inner: &L, env: &EnvKey) {
+        recorder.log(&RecordedEvent::Env {
+            principal: inner.principal(),
+            label: env.label.clone(),
+            fork: env.fork,
+            time_control: inner.time_control().is_some(),
+        });
+    }
+
+    async fn call(
+        &self,
+        kind: CallKind,
+        method: &str,
+        arg: Vec<u8>,
+    ) -> Result<Vec<u8>, LedgerCallError> {
+        let hex_arg = hex::encode(&arg);
+        let reply = match kind {
+            CallKind::Query => self.inner.query_raw(method, arg).await,
+            CallKind::Update => self.inner.update_raw(method, arg).await,
+        };
+
+        self.log(&RecordedEvent::Call(RecordedCall {
+            kind,
+            method: method.to_string(),
+            caller: self.inner.principal(),
+            canister: self.canister,
+            time: time_nanos(self.inner.time()),
+            arg: hex_arg,
+            reply: reply.as_ref().map(hex::encode).map_err(Clone::clone),
+        }));
+
+        reply
+    }
+}
+
+impl<L: LedgerEnv + Clone> LedgerEnv for RecordingLedger<L> {
+    fn fork(&self) -> Self {
+        let inner = self.inner.fork();
+        let env = {
+            let mut recorder = self.recorder.lock().unwrap();
+            let env = recorder.forks.fork(&self.env);
+            Self::log_env(&mut recorder, &inner, &env);
+            env
+        };
+        Self {
+            inner,
+            recorder: self.recorder.clone(),
+            env,
+            canister: self.canister,
+        }
+    }
+
+    fn with_label(&self, label: &str) -> Option<Self> {
+        let inner = self
+            .inner
+            .with_label(label)
+            .unwrap_or_else(|| self.inner.clone());
+        let env = EnvKey {
+            label: Some(label.to_string()),
+            fork: None,
+        };
+        Self::log_env(&mut self.recorder.lock().unwrap(), &inner, &env);
+        Some(Self {
+            inner,
+            recorder: self.recorder.clone(),
+            env,
+            canister: self.canister,
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        self.inner.with_canister(canister_id).map(|inner| Self {
+            inner,
+            recorder: self.recorder.clone(),
+            env: self.env.clone(),
+            canister: Some(canister_id),
+        })
+    }
+
+    fn principal(&self) -> Principal {
+        self.inner.principal()
+    }
+
+    fn time(&self) -> SystemTime {
+        let time = self.inner.time();
+        self.log(&RecordedEvent::Time {
+            label: self.env.label.clone(),
+            fork: self.env.fork,
+            nanos: time_nanos(time),
+        });
+        time
+    }
+
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        self.inner.time_control()
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(self.call(CallKind::Query, method, arg))
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(self.call(CallKind::Update, method, arg))
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async move {
+            let reply = self.inner.canister_metadata(name).await;
+            self.log(&RecordedEvent::Metadata {
+                canister: self.canister,
+                name: name.to_string(),
+                reply: match &reply {
+                    Ok(section) => Ok(section.as_ref().map(hex::encode)),
+                    Err(err) => Err(err.clone()),
+                },
+            });
+            reply
+        })
+    }
+}
+
+struct ReplayState {
+    envs: HashMap<EnvKey, (Principal, bool)>,
+    forks: ForkCounter,
+    times: HashMap<EnvKey, VecDeque<u64>>,
+    last_time: u64,
+    calls: Vec<Option<RecordedCall>>,
+    metadata: Vec<RecordedEvent>,
+}
+
+/// A ledger environment serving the replies of a recording made with a
+/// [RecordingLedger].
+///
+/// Calls are matched to the first unserved recorded call with the same
+/// kind, caller, method and arguments, so the calls may be replayed in a
+/// different order than they were recorded. Labeled environments and forks
+/// get the principals recorded for the same label and fork index, and time
+/// reads get the times recorded by the same environment in the recorded
+/// order, so tests using differently labeled environments may run in any
+/// order. Labeling an environment fails if the recording has no environment
+/// with that label. Metadata reads get the first recorded section with the
+/// same name, and no section if the recording has none. The environments
+/// have time control if the recorded ones had, but moving the time has no
+/// effect.
+#[derive(Clone)]
+pub struct ReplayLedger {
+    principal: Principal,
+    time_control: bool,
+    env: EnvKey,
+    canister: Option<Principal>,
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayLedger {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader(reader: impl Read) -> Result<Self, ReplayError> {
+        let mut envs = HashMap::new();
+        let mut times: HashMap<EnvKey, VecDeque<u64>> = HashMap::new();
+        let mut last_time = 0;
+        let mut calls = vec![];
+        let mut metadata = vec![];
+
+        for (idx, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: RecordedEvent =
+                serde_json::from_str(&line).map_err(|err| ReplayError::Malformed {
+                    line: idx + 1,
+                    message: err.to_string(),
+                })?;
+            match event {
+                RecordedEvent::Env {
+                    principal,
+                    label,
+                    fork,
+                    time_control,
+                } => {
+                    envs.insert(EnvKey { label, fork }, (principal, time_control));
+                }
+                RecordedEvent::Time { label, fork, nanos } => {
+                    last_time = nanos;
+                    times
+                        .entry(EnvKey { label, fork })
+                        .or_default()
+                        .push_back(nanos);
+                }
+                RecordedEvent::Call(call) => {
+                    last_time = call.time;
+                    calls.push(Some(call));
+                }
+                event @ RecordedEvent::Metadata { .. } => metadata.push(event),
+            }
+        }
+
+        let (principal, time_control) = *envs.get(&EnvKey::default()).ok_or(ReplayError::NoEnv)?;
+        Ok(Self {
+            principal,
+            time_control,
+            env: EnvKey::default(),
+            canister: None,
+            state: Arc::new(Mutex::new(ReplayState {
+                envs,
+                forks: ForkCounter::default(),
+                times,
+                last_time,
+                calls,
+                metadata,
+            })),
+        })
+    }
+
+    /// Returns the recorded calls that were not replayed yet.
+    pub fn unserved(&self) -> Vec<RecordedCall> {
+        let state = self.state.lock().unwrap();
+        state.calls.iter().flatten().cloned().collect()
+    }
+
+    fn call(&self, kind: CallKind, method: &str, arg: Vec<u8>) -> Result<Vec<u8>, LedgerCallError> {
+        let arg = hex::encode(arg);
+
+        let call = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .calls
+                .iter_mut()
+                .find(|call| {
+                    call.as_ref().map_or(false, |call| {
+                        call.kind == kind
+                            && call.caller == self.principal
+                            && call.canister == self.canister
+                            && call.method == method
+                            && call.arg == arg
+                    })
+                })
+                .and_then(Option::take)
+        };
+        let call = call.ok_or_else(|| LedgerCallError::Transport {
+            method: method.to_string(),
+            message: format!(
+                "no recorded reply for {:?} call from {} with arguments {}",
+                kind, self.principal, arg
+            ),
+        })?;
+
+        let reply = call.reply?;
+        hex::decode(&reply).map_err(|err| LedgerCallError::Decode {
+            method: method.to_string(),
+            expected: "hex-encoded Candid".to_string(),
+            message: format!("malformed recorded reply: {}", err),
+            reply: reply.into_bytes(),
+        })
+    }
+
+    fn metadata(&self, section: &str) -> Result<Option<Vec<u8>>, LedgerCallError> {
+        let state = self.state.lock().unwrap();
+        let reply = state.metadata.iter().find_map(|event| match event {
+            RecordedEvent::Metadata {
+                canister,
+                name,
+                reply,
+            } if *canister == self.canister && name == section => Some(reply.clone()),
+            _ => None,
+        });
+        match reply {
+            None | Some(Ok(None)) => Ok(None),
+            Some(Err(err)) => Err(err),
+            Some(Ok(Some(hex_section))) => {
+                hex::decode(&hex_section)
+                    .map(Some)
+                    .map_err(|err| LedgerCallError::Decode {
+                        method: section.to_string(),
+                        expected: "hex-encoded metadata".to_string(),
+                        message: format!("malformed recorded metadata: {}", err),
+                        reply: hex_section.into_bytes(),
+                    })
+            }
+        }
+    }
+}
+
+impl LedgerEnv for ReplayLedger {
+    fn fork(&self) -> Self {
+        let mut state = self.state.lock().unwrap();
+        let env = state.forks.fork(&self.env);
+        let (principal, time_control) = *state.envs.get(&env).unwrap_or_else(|| {
+            panic!(
+                "the recording has no fork {} of the environment labeled {:?}",
+                env.fork.unwrap_or_default(),
+                env.label
+            )
+        });
+        Self {
+            principal,
+            time_control,
+            env,
+            canister: self.canister,
+            state: self.state.clone(),
+        }
+    }
+
+    fn with_label(&self, label: &str) -> Option<Self> {
+        let env = EnvKey {
+            label: Some(label.to_string()),
+            fork: None,
+        };
+        let (principal, time_control) = *self.state.lock().unwrap().envs.get(&env)?;
+        Some(Self {
+            principal,
+            time_control,
+            env,
+            canister: self.canister,
+            state: self.state.clone(),
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        Some(Self {
+            principal: self.principal,
+            time_control: self.time_control,
+            env: self.env.clone(),
+            canister: Some(canister_id),
+            state: self.state.clone(),
+        })
+    }
+
+    fn principal(&self) -> Principal {
+        self.principal
+    }
+
+    fn time(&self) -> SystemTime {
+        let mut state = self.state.lock().unwrap();
+        let nanos = match state.times.get_mut(&self.env).and_then(VecDeque::pop_front) {
+            Some(nanos) => nanos,
+            None => state.last_time,
+        };
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)
+    }
+
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        if self.time_control {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move { self.call(CallKind::Query, method, arg) })
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move { self.call(CallKind::Update, method, arg) })
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async move { self.metadata(name) })
+    }
+}
+
+/// Moving the time of a replayed ledger has no effect, the ledger time
+/// follows the recorded times.
+impl TimeControl for ReplayLedger {
+    fn set_time(&self, _time: SystemTime) {}
+
+    fn advance_time(&self, _duration: Duration) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icrc1::{balance_of, transfer};
+    use crate::RejectCode;
+    use crate::{Account, Transfer, TransferError};
+    use candid::utils::encode_args;
+    use candid::Nat;
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Replies to all calls with the number of updates executed so far,
+    /// and fails updates of a method named "fail". Labeled environments
+    /// call with the label as principal.
+    #[derive(Clone)]
+    struct CountingLedger {
+        principal: Principal,
+        updates: Arc<AtomicU64>,
+    }
+
+    impl CountingLedger {
+        fn new() -> Self {
+            Self {
+                principal: Principal::from_slice(&[1]),
+                updates: Arc::new(AtomicU64::new(0)),
+            }
+        }
+    }
+
+    impl LedgerEnv for CountingLedger {
+        fn fork(&self) -> Self {
+            let mut bytes = self.principal.as_slice().to_vec();
+            bytes.push(1);
+            Self {
+                principal: Principal::from_slice(&bytes),
+                updates: self.updates.clone(),
+            }
+        }
+
+        fn with_label(&self, label: &str) -> Option<Self> {
+            Some(Self {
+                principal: Principal::from_slice(label.as_bytes()),
+                updates: self.updates.clone(),
+            })
+        }
+
+        fn with_canister(&self, _canister_id: Principal) -> Option<Self> {
+            Some(self.clone())
+        }
+
+        fn principal(&self) -> Principal {
+            self.principal
+        }
+
+        fn time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(self.updates.load(Ordering::Relaxed))
+        }
+
+        fn query_raw<'a>(
+            &'a self,
+            _method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                Ok(encode_args((Nat::from(self.updates.load(Ordering::Relaxed)),)).unwrap())
+            })
+        }
+
+        fn update_raw<'a>(
+            &'a self,
+            method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                if method == "fail" {
+                    return Err(LedgerCallError::Reject {
+                        method: method.to_string(),
+                        code: RejectCode::CanisterError,
+                        message: "the update failed".to_string(),
+                    });
+                }
+                self.updates.fetch_add(1, Ordering::Relaxed);
+                let reply: (Result<Nat, TransferError>,) =
+                    (Ok(Nat::from(self.updates.load(Ordering::Relaxed))),);
+                Ok(encode_args(reply).unwrap())
+            })
+        }
+
+        fn canister_metadata<'a>(
+            &'a self,
+            name: &'a str,
+        ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+            Box::pin(
+                async move { Ok((name == "candid:service").then(|| b"service : {}".to_vec())) },
+            )
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        let receiver = env.fork();
+        let (recorded_block, recorded_time, recorded_balance) = block_on(async {
+            let block = transfer(&env, Transfer::amount_to(10u8, receiver.principal()))
+                .await
+                .unwrap();
+            let time = env.time();
+            let balance = balance_of(&receiver, receiver.principal()).await.unwrap();
+            let failure: Result<(), LedgerCallError> = env.update("fail", ()).await;
+            assert!(failure.is_err());
+            (block, time, balance)
+        });
+
+        let env = ReplayLedger::from_file(&path).unwrap();
+        assert_eq!(env.principal(), Principal::from_slice(&[1]));
+        let receiver = env.fork();
+        assert_eq!(receiver.principal(), Principal::from_slice(&[1, 1]));
+        block_on(async {
+            assert_eq!(
+                balance_of(&receiver, receiver.principal()).await.unwrap(),
+                recorded_balance
+            );
+            assert_eq!(
+                transfer(&env, Transfer::amount_to(10u8, receiver.principal()))
+                    .await
+                    .unwrap(),
+                recorded_block
+            );
+            assert_eq!(env.time(), recorded_time);
+            assert!(env.time_control().is_none());
+            let failure: Result<(), LedgerCallError> = env.update("fail", ()).await;
+            assert!(matches!(
+                failure,
+                Err(LedgerCallError::Reject {
+                    code: RejectCode::CanisterError,
+                    ..
+                })
+            ));
+        });
+        assert!(env.unserved().is_empty());
+    }
+
+    #[test]
+    fn test_replay_labeled_envs_in_any_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        let a = env.with_label("a").unwrap().fork();
+        let a_time = a.time();
+        let b = env.with_label("b").unwrap();
+        block_on(transfer(&b, Transfer::amount_to(10u8, b.principal())))
+            .unwrap()
+            .unwrap();
+        let b = b.fork();
+        let b_time = b.time();
+        assert_ne!(a_time, b_time);
+
+        let env = ReplayLedger::from_file(&path).unwrap();
+        let b = env.with_label("b").unwrap();
+        block_on(transfer(&b, Transfer::amount_to(10u8, b.principal())))
+            .unwrap()
+            .unwrap();
+        let b = b.fork();
+        assert_eq!(b.principal(), Principal::from_slice(b"b\x01"));
+        assert_eq!(b.time(), b_time);
+        let a = env.with_label("a").unwrap().fork();
+        assert_eq!(a.principal(), Principal::from_slice(b"a\x01"));
+        assert_eq!(a.time(), a_time);
+        assert!(env.with_label("c").is_none());
+        assert!(env.unserved().is_empty());
+    }
+
+    #[test]
+    fn test_replay_unrecorded_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        block_on(transfer(&env, Transfer::amount_to(10u8, env.principal())))
+            .unwrap()
+            .unwrap();
+
+        let env = ReplayLedger::from_file(&path).unwrap();
+        block_on(async {
+            assert!(transfer(&env, Transfer::amount_to(11u8, env.principal()))
+                .await
+                .is_err());
+            assert!(balance_of(&env, Account::from(env.principal()))
+                .await
+                .is_err());
+        });
+        assert_eq!(env.unserved().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_sibling_canister() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+        let archive_id = Principal::from_slice(&[2]);
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        let archive = env.with_canister(archive_id).unwrap();
+        block_on(async {
+            balance_of(&env, env.principal()).await.unwrap();
+            transfer(&env, Transfer::amount_to(10u8, env.principal()))
+                .await
+                .unwrap()
+                .unwrap();
+            balance_of(&archive, env.principal()).await.unwrap();
+        });
+
+        // The calls to the ledger and to the archive have the same caller
+        // and arguments, only the canister tells them apart.
+        let env = ReplayLedger::from_file(&path).unwrap();
+        let archive = env.with_canister(archive_id).unwrap();
+        block_on(async {
+            assert_eq!(
+                balance_of(&archive, env.principal()).await.unwrap(),
+                Nat::from(1u8)
+            );
+            assert_eq!(
+                balance_of(&env, env.principal()).await.unwrap(),
+                Nat::from(0u8)
+            );
+        });
+        assert_eq!(env.unserved().len(), 1);
+    }
+
+    #[test]
+    fn test_replay_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        block_on(async {
+            env.canister_metadata("candid:service").await.unwrap();
+            env.canister_metadata("git_commit_id").await.unwrap();
+        });
+
+        let env = ReplayLedger::from_file(&path).unwrap();
+        block_on(async {
+            assert_eq!(
+                env.canister_metadata("candid:service").await.unwrap(),
+                Some(b"service : {}".to_vec())
+            );
+            assert_eq!(env.canister_metadata("git_commit_id").await.unwrap(), None);
+            assert_eq!(env.canister_metadata("unrecorded").await.unwrap(), None);
+            let archive = env.with_canister(Principal::from_slice(&[2])).unwrap();
+            assert_eq!(
+                archive.canister_metadata("candid:service").await.unwrap(),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn test_finish_returns_write_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.jsonl");
+
+        let env = RecordingLedger::new(CountingLedger::new(), &path).unwrap();
+        env.finish().unwrap();
+        // Writing to a read-only handle fails.
+        env.recorder.lock().unwrap().file = File::open(&path).unwrap();
+        let receiver = env.fork();
+        block_on(transfer(
+            &env,
+            Transfer::amount_to(10u8, receiver.principal()),
+        ))
+        .unwrap()
+        .unwrap();
+        assert!(receiver.finish().is_err());
+    }
+
+    #[test]
+    fn test_malformed_recording() {
+        assert!(matches!(
+            ReplayLedger::from_reader(&b""[..]),
+            Err(ReplayError::NoEnv)
+        ));
+        assert!(matches!(
+            ReplayLedger::from_reader(&b"{\"event\":\"env\",\"principal\":\"aaaaa-aa\"}\n{}"[..]),
+            Err(ReplayError::Malformed { line: 2, .. })
+        ));
+    }
+}
@@ -1,11 +1,11 @@
-use anyhow::Context;
-use async_trait::async_trait;
-use candid::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
 use candid::Principal;
-use ic_agent::identity::BasicIdentity;
-use ic_agent::Agent;
-use icrc1_test_env::LedgerEnv;
-use ring::rand::SystemRandom;
+use ic_agent::agent::RejectCode as AgentRejectCode;
+use ic_agent::identity::{BasicIdentity, Secp256k1Identity};
+use ic_agent::{Agent, AgentError, Identity};
+use icrc1_test_env::{IdentitySeed, LedgerCallError, LedgerEnv, LedgerFuture, RejectCode};
+use k256::SecretKey;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
@@ -19,28 +19,123 @@ pub fn fresh_identity(rand: &SystemRandom) -> BasicIdentity {
     BasicIdentity::from_key_pair(key_pair)
 }
 
+/// The type of the keys of the identities that forked environments use.
+/// Ledgers see the principals of both types as self-authenticating
+/// principals, but derive them from differently encoded public keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum KeyType {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+/// Returns a new identity with a random key of the specified type.
+pub fn fresh_identity_with_key_type(key_type: KeyType, rand: &SystemRandom) -> Box<dyn Identity> {
+    match key_type {
+        KeyType::Ed25519 => Box::new(fresh_identity(rand)),
+        KeyType::Secp256k1 => loop {
+            let mut bytes = [0u8; 32];
+            rand.fill(&mut bytes)
+                .expect("failed to generate a secp256k1 private key");
+            // Fewer than one in 2^127 byte strings are not a valid key.
+            if let Ok(key) = SecretKey::from_slice(&bytes) {
+                break Box::new(Secp256k1Identity::from_private_key(key));
+            }
+        },
+    }
+}
+
+/// Returns the identity of the specified type with the specified key seed,
+/// see [IdentitySeed::key_seed].
+pub fn seeded_identity(key_type: KeyType, key_seed: &[u8; 32]) -> Box<dyn Identity> {
+    match key_type {
+        KeyType::Ed25519 => {
+            use ring::signature::Ed25519KeyPair as KeyPair;
+
+            let key_pair = KeyPair::from_seed_unchecked(key_seed)
+                .expect("failed to construct a key pair from a seed");
+            Box::new(BasicIdentity::from_key_pair(key_pair))
+        }
+        KeyType::Secp256k1 => {
+            let key = SecretKey::from_slice(key_seed)
+                .expect("failed to construct a secp256k1 private key from a seed");
+            Box::new(Secp256k1Identity::from_private_key(key))
+        }
+    }
+}
+
+fn call_error(method: &str, err: AgentError) -> LedgerCallError {
+    match err {
+        AgentError::ReplicaError(reject) => LedgerCallError::Reject {
+            method: method.to_string(),
+            code: match reject.reject_code {
+                AgentRejectCode::SysFatal => RejectCode::SysFatal,
+                AgentRejectCode::SysTransient => RejectCode::SysTransient,
+                AgentRejectCode::DestinationInvalid => RejectCode::DestinationInvalid,
+                AgentRejectCode::CanisterReject => RejectCode::CanisterReject,
+                AgentRejectCode::CanisterError => RejectCode::CanisterError,
+            },
+            message: reject.reject_message,
+        },
+        err => LedgerCallError::Transport {
+            method: method.to_string(),
+            message: err.to_string(),
+        },
+    }
+}
+
 #[derive(Clone)]
 pub struct ReplicaLedger {
     rand: Arc<Mutex<SystemRandom>>,
     agent: Arc<Agent>,
     canister_id: Principal,
+    counter: Arc<AtomicU64>,
+    identity_seed: Option<IdentitySeed>,
+    key_type: KeyType,
 }
 
-#[async_trait(?Send)]
 impl LedgerEnv for ReplicaLedger {
     fn fork(&self) -> Self {
         let mut agent = Arc::clone(&self.agent);
-        Arc::make_mut(&mut agent).set_identity({
-            let r = self.rand.lock().expect("failed to grab a lock");
-            fresh_identity(&r)
+        Arc::make_mut(&mut agent).set_identity(match &self.identity_seed {
+            Some(seed) => {
+                let index = self.counter.fetch_add(1, Ordering::Relaxed);
+                seeded_identity(self.key_type, &seed.key_seed(index))
+            }
+            None => {
+                let r = self.rand.lock().expect("failed to grab a lock");
+                fresh_identity_with_key_type(self.key_type, &r)
+            }
         });
         Self {
             rand: Arc::clone(&self.rand),
             agent,
             canister_id: self.canister_id,
+            counter: Arc::clone(&self.counter),
+            identity_seed: self.identity_seed.clone(),
+            key_type: self.key_type,
         }
     }
 
+    fn with_label(&self, label: &str) -> Option<Self> {
+        let seed = self.identity_seed.as_ref()?.derive(label);
+        Some(Self {
+            rand: Arc::clone(&self.rand),
+            agent: Arc::clone(&self.agent),
+            canister_id: self.canister_id,
+            counter: Arc::new(AtomicU64::new(0)),
+            identity_seed: Some(seed),
+            key_type: self.key_type,
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        Some(Self {
+            canister_id,
+            ..self.clone()
+        })
+    }
+
     fn principal(&self) -> Principal {
         self.agent
             .get_principal()
@@ -54,65 +149,50 @@ impl LedgerEnv for ReplicaLedger {
         SystemTime::now()
     }
 
-    async fn query<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
-    where
-        Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>,
-    {
-        let debug_inputs = format!("{:?}", input);
-        let in_bytes = encode_args(input)
-            .with_context(|| format!("Failed to encode arguments {}", debug_inputs))?;
-        let bytes = self
-            .agent
-            .query(&self.canister_id, method)
-            .with_arg(in_bytes)
-            .call()
-            .await
-            .with_context(|| {
-                format!(
-                    "failed to call method {} on {} with args {}",
-                    method, self.canister_id, debug_inputs
-                )
-            })?;
-
-        decode_args(&bytes).with_context(|| {
-            format!(
-                "Failed to decode method {} response into type {}, bytes: {}",
-                method,
-                std::any::type_name::<Output>(),
-                hex::encode(bytes)
-            )
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            self.agent
+                .query(&self.canister_id, method)
+                .with_arg(arg)
+                .call()
+                .await
+                .map_err(|err| call_error(method, err))
+        })
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            self.agent
+                .update(&self.canister_id, method)
+                .with_arg(arg)
+                .call_and_wait()
+                .await
+                .map_err(|err| call_error(method, err))
         })
     }
 
-    async fn update<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
-    where
-        Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>,
-    {
-        let debug_inputs = format!("{:?}", input);
-        let in_bytes = encode_args(input)
-            .with_context(|| format!("Failed to encode arguments {}", debug_inputs))?;
-        let bytes = self
-            .agent
-            .update(&self.canister_id, method)
-            .with_arg(in_bytes)
-            .call_and_wait()
-            .await
-            .with_context(|| {
-                format!(
-                    "failed to call method {} on {} with args {}",
-                    method, self.canister_id, debug_inputs
-                )
-            })?;
-
-        decode_args(&bytes).with_context(|| {
-            format!(
-                "Failed to decode method {} response into type {}, bytes: {}",
-                method,
-                std::any::type_name::<Output>(),
-                hex::encode(bytes)
-            )
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async move {
+            match self
+                .agent
+                .read_state_canister_metadata(self.canister_id, name)
+                .await
+            {
+                Ok(section) => Ok(Some(section)),
+                Err(AgentError::LookupPathAbsent(_)) => Ok(None),
+                Err(err) => Err(call_error(name, err)),
+            }
         })
     }
 }
@@ -123,6 +203,27 @@ impl ReplicaLedger {
             rand: Arc::new(Mutex::new(SystemRandom::new())),
             agent: Arc::new(agent),
             canister_id,
+            counter: Arc::new(AtomicU64::new(0)),
+            identity_seed: None,
+            key_type: KeyType::default(),
         }
     }
+
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    /// Derives the identities of forked environments from the specified
+    /// seed instead of generating random keys, see [LedgerEnv::with_label].
+    pub fn with_identity_seed(mut self, seed: IdentitySeed) -> Self {
+        self.identity_seed = Some(seed);
+        self
+    }
+
+    /// Gives forked environments identities with keys of the specified
+    /// type. Forks use Ed25519 keys by default.
+    pub fn with_key_type(mut self, key_type: KeyType) -> Self {
+        self.key_type = key_type;
+        self
+    }
 }
@@ -0,0 +1,85 @@
+//! Deriving the identities of forked environments from a seed.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// A seed from which ledger environments derive the keys of the callers
+/// they fork, so that the callers are the same in every run.
+///
+/// Seeds form a hierarchy: [IdentitySeed::derive] returns the seed for a
+/// label, like a test name, and [IdentitySeed::key_seed] returns the key
+/// material of the n-th caller forked with a seed. The callers of a test
+/// can be re-derived later from the master seed and the test name.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct IdentitySeed([u8; 32]);
+
+impl IdentitySeed {
+    /// Creates the master seed from arbitrary bytes, like a passphrase.
+    pub fn from_master(bytes: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"icrc1-test-env:master");
+        hasher.update(bytes);
+        Self(hasher.finalize().into())
+    }
+
+    /// Returns the seed for the specified label.
+    pub fn derive(&self, label: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"icrc1-test-env:label");
+        hasher.update(self.0);
+        hasher.update(label.as_bytes());
+        Self(hasher.finalize().into())
+    }
+
+    /// Returns the Ed25519 key seed of the caller with the specified index.
+    pub fn key_seed(&self, index: u64) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"icrc1-test-env:key");
+        hasher.update(self.0);
+        hasher.update(index.to_be_bytes());
+        hasher.finalize().into()
+    }
+}
+
+impl fmt::Debug for IdentitySeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The seed is secret, only show a fingerprint.
+        let fingerprint = Sha256::digest(self.0);
+        write!(f, "IdentitySeed({})", hex::encode(&fingerprint[..4]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derivation_is_deterministic() {
+        let master = IdentitySeed::from_master(b"correct horse battery staple");
+        assert_eq!(
+            master,
+            IdentitySeed::from_master(b"correct horse battery staple")
+        );
+        assert_ne!(master, IdentitySeed::from_master(b"correct horse battery"));
+
+        let seed = master.derive("icrc1:transfer");
+        assert_eq!(seed, master.derive("icrc1:transfer"));
+        assert_ne!(seed, master.derive("icrc1:burn"));
+        assert_ne!(seed.derive("a").derive("b"), seed.derive("ab"));
+
+        assert_eq!(
+            seed.key_seed(0),
+            master.derive("icrc1:transfer").key_seed(0)
+        );
+        assert_ne!(seed.key_seed(0), seed.key_seed(1));
+        assert_ne!(seed.key_seed(0), master.derive("icrc1:burn").key_seed(0));
+    }
+
+    #[test]
+    fn test_debug_hides_seed() {
+        let seed = IdentitySeed([7; 32]);
+        let debug = format!("{:?}", seed);
+        assert!(!debug.contains(&hex::encode([7; 32])[..8]), "{}", debug);
+        assert!(debug.starts_with("IdentitySeed("));
+    }
+}
@@ -0,0 +1,44 @@
+//! Bounds that the `send` feature turns into `Send` and `Sync`.
+//!
+//! Without the feature, ledger environments and their futures may use
+//! thread-local types like `Rc`. With the feature, they must be `Send` and
+//! `Sync`, so that tests can run on a multi-threaded runtime.
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// The future returned by [crate::LedgerEnv] calls.
+#[cfg(feature = "send")]
+pub type LedgerFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The future returned by [crate::LedgerEnv] calls.
+#[cfg(not(feature = "send"))]
+pub type LedgerFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// `Send` with the `send` feature, implemented by all types otherwise.
+#[cfg(feature = "send")]
+pub trait MaybeSend: Send {}
+
+#[cfg(feature = "send")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// `Send` with the `send` feature, implemented by all types otherwise.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// `Sync` with the `send` feature, implemented by all types otherwise.
+#[cfg(feature = "send")]
+pub trait MaybeSync: Sync {}
+
+#[cfg(feature = "send")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// `Sync` with the `send` feature, implemented by all types otherwise.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSync for T {}
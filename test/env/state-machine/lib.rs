@@ -1,11 +1,13 @@
-use anyhow::Context;
-use async_trait::async_trait;
-use candid::utils::{decode_args, encode_args, ArgumentDecoder, ArgumentEncoder};
 use candid::Principal;
-use ic_test_state_machine_client::StateMachine;
-use icrc1_test_env::LedgerEnv;
+use ic_test_state_machine_client::{StateMachine, UserError, WasmResult};
+use icrc1_test_env::{
+    IdentitySeed, LedgerCallError, LedgerEnv, LedgerFuture, RejectCode, TimeControl,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
 
 fn new_principal(n: u64) -> Principal {
     let mut bytes = n.to_le_bytes().to_vec();
@@ -14,113 +16,268 @@ fn new_principal(n: u64) -> Principal {
     Principal::try_from_slice(&bytes[..]).unwrap()
 }
 
+/// Returns the self-authenticating principal of the Ed25519 key with the
+/// specified seed, the same principal that an agent signing with this key
+/// uses.
+pub fn seeded_principal(key_seed: &[u8; 32]) -> Principal {
+    const ED25519_DER_PREFIX: [u8; 12] = [
+        0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+    ];
+    let key_pair = Ed25519KeyPair::from_seed_unchecked(key_seed).unwrap();
+    let mut der = ED25519_DER_PREFIX.to_vec();
+    der.extend_from_slice(key_pair.public_key().as_ref());
+    Principal::self_authenticating(der)
+}
+
+/// Returns the canister metadata sections of a Wasm module, the custom
+/// sections named `icp:public <name>` or `icp:private <name>`, by name.
+/// Returns no sections if the module is malformed or compressed.
+pub fn wasm_metadata(wasm: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    fn leb128(bytes: &[u8], pos: &mut usize) -> Option<usize> {
+        let mut value = 0usize;
+        for shift in (0..35).step_by(7) {
+            let byte = *bytes.get(*pos)?;
+            *pos += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    let mut metadata = BTreeMap::new();
+    if !wasm.starts_with(b"\0asm") {
+        return metadata;
+    }
+    let mut pos = 8;
+    while pos < wasm.len() {
+        let id = wasm[pos];
+        pos += 1;
+        let section = match leb128(wasm, &mut pos).and_then(|size| wasm.get(pos..pos + size)) {
+            Some(section) => section,
+            None => break,
+        };
+        pos += section.len();
+        if id != 0 {
+            continue;
+        }
+        let mut name_pos = 0;
+        let name = match leb128(section, &mut name_pos)
+            .and_then(|size| section.get(name_pos..name_pos + size))
+            .and_then(|name| std::str::from_utf8(name).ok())
+        {
+            Some(name) => name,
+            None => break,
+        };
+        let content = &section[name_pos + name.len()..];
+        if let Some(name) = name
+            .strip_prefix("icp:public ")
+            .or_else(|| name.strip_prefix("icp:private "))
+        {
+            metadata.insert(name.to_string(), content.to_vec());
+        }
+    }
+    metadata
+}
+
 #[derive(Clone)]
 pub struct SMLedger {
     counter: Arc<AtomicU64>,
-    sm: Arc<StateMachine>,
+    sm: Arc<Mutex<StateMachine>>,
     sender: Principal,
     canister_id: Principal,
+    identity_seed: Option<IdentitySeed>,
+    metadata: Arc<BTreeMap<String, Vec<u8>>>,
 }
 
-#[async_trait(?Send)]
 impl LedgerEnv for SMLedger {
     fn fork(&self) -> Self {
+        let index = self.counter.fetch_add(1, Ordering::Relaxed);
+        let sender = match &self.identity_seed {
+            Some(seed) => seeded_principal(&seed.key_seed(index)),
+            None => new_principal(index),
+        };
         Self {
             counter: self.counter.clone(),
             sm: self.sm.clone(),
-            sender: new_principal(self.counter.fetch_add(1, Ordering::Relaxed)),
+            sender,
             canister_id: self.canister_id,
+            identity_seed: self.identity_seed.clone(),
+            metadata: self.metadata.clone(),
         }
     }
 
+    fn with_label(&self, label: &str) -> Option<Self> {
+        let seed = self.identity_seed.as_ref()?.derive(label);
+        Some(Self {
+            counter: Arc::new(AtomicU64::new(0)),
+            sm: self.sm.clone(),
+            sender: self.sender,
+            canister_id: self.canister_id,
+            identity_seed: Some(seed),
+            metadata: self.metadata.clone(),
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        Some(Self {
+            canister_id,
+            metadata: Default::default(),
+            ..self.clone()
+        })
+    }
+
     fn principal(&self) -> Principal {
         self.sender
     }
 
-    fn time(&self) -> std::time::SystemTime {
-        self.sm.time()
-    }
-
-    async fn query<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
-    where
-        Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>,
-    {
-        let debug_inputs = format!("{:?}", input);
-        let in_bytes = encode_args(input)
-            .with_context(|| format!("Failed to encode arguments {}", debug_inputs))?;
-        match self
-            .sm
-            .query_call(
-                Principal::from_slice(self.canister_id.as_slice()),
-                Principal::from_slice(self.sender.as_slice()),
-                method,
-                in_bytes,
-            )
-            .map_err(|err| anyhow::Error::msg(err.to_string()))?
-        {
-            ic_test_state_machine_client::WasmResult::Reply(bytes) => decode_args(&bytes)
-                .with_context(|| {
-                    format!(
-                        "Failed to decode method {} response into type {}, bytes: {}",
-                        method,
-                        std::any::type_name::<Output>(),
-                        hex::encode(bytes)
-                    )
-                }),
-            ic_test_state_machine_client::WasmResult::Reject(msg) => {
-                return Err(anyhow::Error::msg(format!(
-                    "Query call to ledger {:?} was rejected: {}",
-                    self.canister_id, msg
-                )))
-            }
-        }
+    fn time(&self) -> SystemTime {
+        self.sm().time()
     }
 
-    async fn update<Input, Output>(&self, method: &str, input: Input) -> anyhow::Result<Output>
-    where
-        Input: ArgumentEncoder + std::fmt::Debug,
-        Output: for<'a> ArgumentDecoder<'a>,
-    {
-        let debug_inputs = format!("{:?}", input);
-        let in_bytes = encode_args(input)
-            .with_context(|| format!("Failed to encode arguments {}", debug_inputs))?;
-        match self
-            .sm
-            .update_call(self.canister_id, self.sender, method, in_bytes)
-            .map_err(|err| anyhow::Error::msg(err.to_string()))
-            .with_context(|| {
-                format!(
-                    "failed to execute update call {} on canister {}",
-                    method, self.canister_id
-                )
-            })? {
-            ic_test_state_machine_client::WasmResult::Reply(bytes) => decode_args(&bytes)
-                .with_context(|| {
-                    format!(
-                        "Failed to decode method {} response into type {}, bytes: {}",
-                        method,
-                        std::any::type_name::<Output>(),
-                        hex::encode(bytes)
-                    )
-                }),
-            ic_test_state_machine_client::WasmResult::Reject(msg) => {
-                return Err(anyhow::Error::msg(format!(
-                    "Query call to ledger {:?} was rejected: {}",
-                    self.canister_id, msg
-                )))
-            }
-        }
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        Some(self)
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            let result = self
+                .sm()
+                .query_call(self.canister_id, self.sender, method, arg);
+            self.reply(method, result)
+        })
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(async move {
+            let result = self
+                .sm()
+                .update_call(self.canister_id, self.sender, method, arg);
+            self.reply(method, result)
+        })
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        Box::pin(async move { Ok(self.metadata.get(name).cloned()) })
+    }
+}
+
+impl TimeControl for SMLedger {
+    fn set_time(&self, time: SystemTime) {
+        self.sm().set_time(time);
+    }
+
+    fn advance_time(&self, duration: Duration) {
+        self.sm().advance_time(duration);
     }
 }
 
 impl SMLedger {
-    pub fn new(sm: Arc<StateMachine>, canister_id: Principal, sender: Principal) -> Self {
+    /// Creates an environment calling the specified canister as `sender`.
+    /// The environment takes ownership of the state machine, so that it can
+    /// be shared between threads.
+    pub fn new(sm: StateMachine, canister_id: Principal, sender: Principal) -> Self {
         Self {
             counter: Arc::new(AtomicU64::new(0)),
-            sm,
+            sm: Arc::new(Mutex::new(sm)),
             canister_id,
             sender,
+            identity_seed: None,
+            metadata: Default::default(),
+        }
+    }
+
+    /// Derives the callers of forked environments from the specified seed
+    /// instead of a counter, see [LedgerEnv::with_label].
+    pub fn with_identity_seed(mut self, seed: IdentitySeed) -> Self {
+        self.identity_seed = Some(seed);
+        self
+    }
+
+    /// Serves the canister metadata of the specified Wasm module, the
+    /// module installed on the ledger. The state machine does not expose
+    /// the metadata of installed canisters.
+    pub fn with_wasm_metadata(mut self, wasm: &[u8]) -> Self {
+        self.metadata = Arc::new(wasm_metadata(wasm));
+        self
+    }
+
+    pub fn canister_id(&self) -> Principal {
+        self.canister_id
+    }
+
+    /// Returns the root key of the state machine, which signs the
+    /// certificates of the ledger.
+    pub fn root_key(&self) -> Vec<u8> {
+        self.sm().root_key()
+    }
+
+    fn sm(&self) -> MutexGuard<'_, StateMachine> {
+        self.sm.lock().unwrap()
+    }
+
+    fn reply(
+        &self,
+        method: &str,
+        result: Result<WasmResult, UserError>,
+    ) -> Result<Vec<u8>, LedgerCallError> {
+        match result {
+            Ok(WasmResult::Reply(bytes)) => Ok(bytes),
+            Ok(WasmResult::Reject(message)) => Err(LedgerCallError::Reject {
+                method: method.to_string(),
+                code: RejectCode::CanisterReject,
+                message,
+            }),
+            // The first digit of an error code is the reject code.
+            Err(err) => Err(LedgerCallError::Reject {
+                method: method.to_string(),
+                code: RejectCode::from_code(err.code as u64 / 100).unwrap_or(RejectCode::SysFatal),
+                message: format!("call to ledger {} failed: {}", self.canister_id, err),
+            }),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_section(name: &str, content: &[u8]) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(content);
+        let mut bytes = vec![0, section.len() as u8];
+        bytes.extend(section);
+        bytes
+    }
+
+    #[test]
+    fn test_wasm_metadata() {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend(custom_section("icp:public candid:service", b"service : {}"));
+        // A type section with no types.
+        wasm.extend([1, 1, 0]);
+        wasm.extend(custom_section("icp:private git_commit_id", b"abc"));
+        wasm.extend(custom_section("name", b"\0"));
+
+        let metadata = wasm_metadata(&wasm);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["candid:service"], b"service : {}");
+        assert_eq!(metadata["git_commit_id"], b"abc");
+
+        assert!(wasm_metadata(&[0x1f, 0x8b, 0x08]).is_empty());
+        assert!(wasm_metadata(&wasm[..wasm.len() - 1]).len() == 2);
+    }
+}
@@ -0,0 +1,146 @@
+//! Helpers for checking how operations change the token supply.
+
+use crate::icrc1::{balance_of, minting_account, total_supply};
+use crate::{LedgerCallError, LedgerEnv};
+use candid::{Int, Nat};
+use std::future::Future;
+
+/// The total supply and the balance of the minting account at a point in time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupplySnapshot {
+    pub total_supply: Nat,
+    /// The balance of the minting account, zero if the ledger has none.
+    pub minting_account_balance: Nat,
+}
+
+impl SupplySnapshot {
+    pub async fn take(ledger: &impl LedgerEnv) -> Result<Self, LedgerCallError> {
+        let total_supply = total_supply(ledger).await?;
+        let minting_account_balance = match minting_account(ledger).await? {
+            Some(account) => balance_of(ledger, account).await?,
+            None => Nat::from(0u8),
+        };
+        Ok(Self {
+            total_supply,
+            minting_account_balance,
+        })
+    }
+}
+
+/// The supply snapshots taken before and after an operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SupplyChange {
+    pub before: SupplySnapshot,
+    pub after: SupplySnapshot,
+}
+
+impl SupplyChange {
+    /// Returns the change of the total supply, positive for mints and
+    /// negative for burns (including burned fees).
+    pub fn total_supply_delta(&self) -> Int {
+        Int::from(self.after.total_supply.clone()) - Int::from(self.before.total_supply.clone())
+    }
+
+    /// Returns the change of the minting account balance.
+    pub fn minting_account_delta(&self) -> Int {
+        Int::from(self.after.minting_account_balance.clone())
+            - Int::from(self.before.minting_account_balance.clone())
+    }
+
+    /// Returns the amount by which the total supply grew, zero if it did not.
+    pub fn minted(&self) -> Nat {
+        if self.after.total_supply > self.before.total_supply {
+            self.after.total_supply.clone() - self.before.total_supply.clone()
+        } else {
+            Nat::from(0u8)
+        }
+    }
+
+    /// Returns the amount by which the total supply shrank, zero if it did not.
+    pub fn burned(&self) -> Nat {
+        if self.before.total_supply > self.after.total_supply {
+            self.before.total_supply.clone() - self.after.total_supply.clone()
+        } else {
+            Nat::from(0u8)
+        }
+    }
+}
+
+/// Runs the operation and returns its result along with the supply
+/// snapshots taken right before and right after it.
+///
+/// ```
+/// # use candid::{Nat, Principal};
+/// # use icrc1_test_env::icrc1::{transfer, transfer_fee};
+/// # use icrc1_test_env::{with_supply_snapshots, Account, LedgerEnv, Transfer};
+/// # use icrc1_test_env_in_memory::{InMemoryLedger, InitArgs};
+/// # futures::executor::block_on(async {
+/// # let p1 = Principal::from_slice(&[1]);
+/// # let ledger = InMemoryLedger::new(
+/// #     InitArgs {
+/// #         minting_account: Account::from(Principal::from_slice(&[0])),
+/// #         initial_mints: vec![(Account::from(p1), Nat::from(1_000_000u64))],
+/// #         token_name: "Test Token".to_string(),
+/// #         token_symbol: "XTK".to_string(),
+/// #         decimals: 8,
+/// #         transfer_fee: Nat::from(10_000u64),
+/// #     },
+/// #     p1,
+/// # );
+/// let p2 = ledger.fork().principal();
+/// let fee = transfer_fee(&ledger).await?;
+/// let (result, change) =
+///     with_supply_snapshots(&ledger, || transfer(&ledger, Transfer::amount_to(1_000u64, p2)))
+///         .await?;
+/// result??;
+/// assert_eq!(change.burned(), fee);
+/// # Ok::<(), anyhow::Error>(())
+/// # })
+/// # .unwrap();
+/// ```
+pub async fn with_supply_snapshots<L, F, Fut, T>(
+    ledger: &L,
+    operation: F,
+) -> Result<(T, SupplyChange), LedgerCallError>
+where
+    L: LedgerEnv,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let before = SupplySnapshot::take(ledger).await?;
+    let result = operation().await;
+    let after = SupplySnapshot::take(ledger).await?;
+    Ok((result, SupplyChange { before, after }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(total_supply: u64, minting_account_balance: u64) -> SupplySnapshot {
+        SupplySnapshot {
+            total_supply: Nat::from(total_supply),
+            minting_account_balance: Nat::from(minting_account_balance),
+        }
+    }
+
+    #[test]
+    fn test_supply_change() {
+        let burn = SupplyChange {
+            before: snapshot(1_000, 0),
+            after: snapshot(990, 0),
+        };
+        assert_eq!(burn.total_supply_delta(), Int::from(-10));
+        assert_eq!(burn.minting_account_delta(), Int::from(0));
+        assert_eq!(burn.burned(), Nat::from(10u8));
+        assert_eq!(burn.minted(), Nat::from(0u8));
+
+        let mint = SupplyChange {
+            before: snapshot(990, 0),
+            after: snapshot(1_490, 0),
+        };
+        assert_eq!(mint.total_supply_delta(), Int::from(500));
+        assert_eq!(mint.minted(), Nat::from(500u16));
+        assert_eq!(mint.burned(), Nat::from(0u8));
+    }
+}
@@ -0,0 +1,131 @@
+//! Named subaccounts of test principals.
+
+use crate::icrc1::{balance_of, transfer};
+use crate::{Account, LedgerCallError, LedgerEnv, Subaccount, Transfer, TransferError};
+use anyhow::{bail, Context};
+use candid::Nat;
+use sha2::{Digest, Sha256};
+
+/// Returns the subaccount with the specified name. Different names map to
+/// different subaccounts, none of which is the default subaccount.
+pub fn named_subaccount(name: &str) -> Subaccount {
+    let mut hasher = Sha256::new();
+    hasher.update(b"icrc1-test-env:subaccount");
+    hasher.update(name.as_bytes());
+    hasher.finalize().into()
+}
+
+/// A named subaccount of the caller of an environment, together with the
+/// environment that owns it.
+#[derive(Clone, Debug)]
+pub struct TestAccount<L> {
+    env: L,
+    name: String,
+    account: Account,
+}
+
+impl<L: LedgerEnv> TestAccount<L> {
+    /// Returns the subaccount with the specified name of the caller of
+    /// `env`, see [named_subaccount].
+    pub fn new(env: L, name: impl Into<String>) -> Self {
+        let name = name.into();
+        let account = Account {
+            owner: env.principal(),
+            subaccount: Some(named_subaccount(&name)),
+        };
+        Self { env, name, account }
+    }
+
+    pub fn env(&self) -> &L {
+        &self.env
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn account(&self) -> Account {
+        self.account.clone()
+    }
+
+    pub fn subaccount(&self) -> Subaccount {
+        self.account.subaccount.unwrap_or_default()
+    }
+
+    pub async fn balance(&self) -> Result<Nat, LedgerCallError> {
+        balance_of(&self.env, self.account()).await
+    }
+
+    /// Returns a transfer from this account.
+    pub fn transfer_arg(&self, amount: impl Into<Nat>, to: impl Into<Account>) -> Transfer {
+        Transfer::amount_to(amount, to).from_subaccount(self.subaccount())
+    }
+
+    /// Transfers the specified amount from this account.
+    pub async fn transfer(
+        &self,
+        amount: impl Into<Nat>,
+        to: impl Into<Account>,
+    ) -> Result<Result<Nat, TransferError>, LedgerCallError> {
+        transfer(&self.env, self.transfer_arg(amount, to)).await
+    }
+}
+
+impl<L: LedgerEnv + Clone> TestAccount<L> {
+    /// Returns another named subaccount of the same owner.
+    pub fn sibling(&self, name: impl Into<String>) -> Self {
+        Self::new(self.env.clone(), name)
+    }
+}
+
+impl<L> From<&TestAccount<L>> for Account {
+    fn from(account: &TestAccount<L>) -> Self {
+        account.account.clone()
+    }
+}
+
+/// Forks a new principal and funds its subaccounts with the specified names
+/// and amounts from the default account of the caller of `funder`. Returns
+/// the funded accounts in the order of `amounts`.
+///
+/// Seeded environments fork the same principal in every run, so the
+/// subaccounts may still hold funds from an earlier run. Each subaccount
+/// receives `amount` on top of its current balance.
+pub async fn fund_subaccounts<L: LedgerEnv + Clone>(
+    funder: &L,
+    amounts: &[(&str, Nat)],
+) -> anyhow::Result<Vec<TestAccount<L>>> {
+    let owner = funder.fork();
+    let mut accounts = Vec::with_capacity(amounts.len());
+    for (name, amount) in amounts {
+        let account = TestAccount::new(owner.clone(), *name);
+        let start = account.balance().await?;
+        transfer(funder, Transfer::amount_to(amount.clone(), &account))
+            .await?
+            .with_context(|| format!("failed to fund subaccount {}", name))?;
+        let balance = account.balance().await?;
+        if balance != start.clone() + amount.clone() {
+            bail!(
+                "subaccount {} has balance {} after funding it with {} on top of {}",
+                name,
+                balance,
+                amount,
+                start
+            );
+        }
+        accounts.push(account);
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_subaccount() {
+        assert_eq!(named_subaccount("savings"), named_subaccount("savings"));
+        assert_ne!(named_subaccount("savings"), named_subaccount("checking"));
+        assert_ne!(named_subaccount(""), [0; 32]);
+    }
+}
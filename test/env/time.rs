@@ -0,0 +1,17 @@
+//! Moving the ledger time in environments that control the ledger clock.
+
+use crate::{MaybeSend, MaybeSync};
+use std::time::{Duration, SystemTime};
+
+/// Controls the time of the ledger, see [crate::LedgerEnv::time_control].
+///
+/// The ledger time is shared by all environments calling the same ledger,
+/// so moving it affects concurrently running tests.
+pub trait TimeControl: MaybeSend + MaybeSync {
+    /// Sets the ledger time. Not all environments support moving the time
+    /// backwards.
+    fn set_time(&self, time: SystemTime);
+
+    /// Moves the ledger time forward.
+    fn advance_time(&self, duration: Duration);
+}
@@ -0,0 +1,512 @@
+//! A `LedgerEnv` decorator tracing calls and collecting latency metrics.
+
+use crate::{
+    decode_call_reply, encode_call_args, LedgerCallError, LedgerEnv, LedgerFuture, TimeControl,
+};
+use candid::types::value::{IDLArgs, IDLValue};
+use candid::types::Label;
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::Principal;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::field::Empty;
+use tracing::Instrument;
+
+/// The upper bounds of the latency histogram buckets, in milliseconds.
+/// Latencies above the last bound fall into an extra overflow bucket.
+const BUCKET_BOUNDS_MS: [u64; 15] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1_000, 2_000, 5_000, 10_000, 20_000, 60_000,
+];
+
+/// The variant names of the ICRC-1 and ICRC-2 results, used to name the
+/// outcomes of calls. Candid replies only carry hashes of the names.
+const KNOWN_VARIANTS: [&str; 13] = [
+    "Ok",
+    "Err",
+    "BadFee",
+    "BadBurn",
+    "InsufficientFunds",
+    "TooOld",
+    "CreatedInFuture",
+    "Duplicate",
+    "TemporarilyUnavailable",
+    "GenericError",
+    "InsufficientAllowance",
+    "AllowanceChanged",
+    "Expired",
+];
+
+/// A histogram of call latencies with fixed buckets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: Duration,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKET_BOUNDS_MS.len() + 1],
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let bucket = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| latency <= Duration::from_millis(*bound))
+            .unwrap_or(BUCKET_BOUNDS_MS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            n => Some(self.total / n as u32),
+        }
+    }
+
+    /// Returns an upper bound of the `q`-quantile of the latencies, that is
+    /// the upper bound of the bucket containing it, or `None` if no
+    /// latencies were recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, bound) in self.buckets() {
+            seen += bucket;
+            if seen >= rank {
+                return Some(bound.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Returns the number of latencies in each bucket together with the
+    /// upper bound of the bucket. The bound of the overflow bucket is the
+    /// maximum latency.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, Duration)> + '_ {
+        let bounds = BUCKET_BOUNDS_MS
+            .iter()
+            .map(|bound| Duration::from_millis(*bound))
+            .chain(std::iter::once(self.max));
+        self.counts.iter().copied().zip(bounds)
+    }
+}
+
+/// The calls made to a single method.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+    pub calls: u64,
+    /// The number of calls that failed with a [LedgerCallError].
+    pub errors: u64,
+    /// The number of calls with each outcome, see [TracingLedger].
+    pub outcomes: BTreeMap<String, u64>,
+    pub latency: LatencyHistogram,
+}
+
+fn variant_name(label: &Label) -> String {
+    match label {
+        Label::Named(name) => name.clone(),
+        Label::Id(id) | Label::Unnamed(id) => KNOWN_VARIANTS
+            .iter()
+            .find(|name| candid::idl_hash(name) == *id)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("#{}", id)),
+    }
+}
+
+/// Describes a reply by the variants it consists of, like `Ok` or
+/// `Err(InsufficientFunds)`.
+fn outcome(reply: &[u8]) -> String {
+    fn describe(value: &IDLValue) -> Option<String> {
+        match value {
+            IDLValue::Variant(variant) => {
+                let name = variant_name(&variant.0.id);
+                Some(match describe(&variant.0.val) {
+                    Some(inner) => format!("{}({})", name, inner),
+                    None => name,
+                })
+            }
+            _ => None,
+        }
+    }
+    match IDLArgs::from_bytes(reply)
+        .as_ref()
+        .map(|reply| &reply.args[..])
+    {
+        Ok([value]) => describe(value).unwrap_or_else(|| "reply".to_string()),
+        _ => "reply".to_string(),
+    }
+}
+
+fn error_outcome(err: &LedgerCallError) -> String {
+    match err {
+        LedgerCallError::Encode { .. } => "encode_error".to_string(),
+        LedgerCallError::Transport { .. } => "transport_error".to_string(),
+        LedgerCallError::Reject { code, .. } => format!("reject({:?})", code),
+        LedgerCallError::Decode { .. } => "decode_error".to_string(),
+        LedgerCallError::Injected(_) => "injected_fault".to_string(),
+    }
+}
+
+/// A `LedgerEnv` decorator that emits a `ledger_call` span for each call
+/// and collects per-method metrics.
+///
+/// The span records the method, the caller, the arguments, the outcome,
+/// and the latency in milliseconds. The outcome of a failed call is the
+/// kind of the error, like `transport_error` or `reject(CanisterError)`.
+/// The outcome of a reply is its variants, like `Err(InsufficientFunds)`,
+/// or `reply` if it is not a variant.
+///
+/// Forked environments share the metrics with the original one.
+#[derive(Clone)]
+pub struct TracingLedger<L> {
+    inner: L,
+    metrics: Arc<Mutex<BTreeMap<String, MethodMetrics>>>,
+}
+
+impl<L: LedgerEnv> TracingLedger<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            metrics: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Returns the metrics of the calls made so far, by method.
+    pub fn metrics(&self) -> BTreeMap<String, MethodMetrics> {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// Traces a call with the specified encoded arguments, or the error
+    /// encoding them, and their description for the span.
+    async fn call(
+        &self,
+        is_update: bool,
+        method: &str,
+        args: String,
+        arg: Result<Vec<u8>, LedgerCallError>,
+    ) -> Result<Vec<u8>, LedgerCallError> {
+        let span = tracing::info_span!(
+            "ledger_call",
+            method,
+            update = is_update,
+            caller = %self.inner.principal(),
+            args = %args,
+            outcome = Empty,
+            latency_ms = Empty,
+        );
+
+        let start = Instant::now();
+        let reply = match arg {
+            Ok(arg) if is_update => {
+                self.inner
+                    .update_raw(method, arg)
+                    .instrument(span.clone())
+                    .await
+            }
+            Ok(arg) => {
+                self.inner
+                    .query_raw(method, arg)
+                    .instrument(span.clone())
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let latency = start.elapsed();
+
+        let outcome = match &reply {
+            Ok(reply) => outcome(reply),
+            Err(err) => error_outcome(err),
+        };
+        span.record("outcome", outcome.as_str());
+        span.record("latency_ms", latency.as_millis() as u64);
+        if let Err(err) = &reply {
+            tracing::warn!(parent: &span, "{}", err);
+        }
+
+        {
+            let mut metrics = self.metrics.lock().unwrap();
+            let metrics = metrics.entry(method.to_string()).or_default();
+            metrics.calls += 1;
+            if reply.is_err() {
+                metrics.errors += 1;
+            }
+            *metrics.outcomes.entry(outcome).or_default() += 1;
+            metrics.latency.record(latency);
+        }
+
+        reply
+    }
+
+    async fn call_raw(
+        &self,
+        is_update: bool,
+        method: &str,
+        arg: Vec<u8>,
+    ) -> Result<Vec<u8>, LedgerCallError> {
+        let args = match IDLArgs::from_bytes(&arg) {
+            Ok(args) => args.to_string(),
+            Err(_) => format!("invalid Candid {}", hex::encode(&arg)),
+        };
+        self.call(is_update, method, args, Ok(arg)).await
+    }
+
+    fn call_typed<'a, Input, Output>(
+        &'a self,
+        is_update: bool,
+        method: &'a str,
+        input: Input,
+    ) -> LedgerFuture<'a, Result<Output, LedgerCallError>>
+    where
+        Input: ArgumentEncoder + std::fmt::Debug,
+        Output: for<'b> ArgumentDecoder<'b> + 'a,
+    {
+        let args = format!("{:?}", input);
+        let arg = encode_call_args(method, input);
+        Box::pin(async move {
+            let reply = self.call(is_update, method, args, arg).await?;
+            decode_call_reply(method, &reply)
+        })
+    }
+}
+
+impl<L: LedgerEnv> LedgerEnv for TracingLedger<L> {
+    fn fork(&self) -> Self {
+        Self {
+            inner: self.inner.fork(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    fn with_label(&self, label: &str) -> Option<Self> {
+        self.inner.with_label(label).map(|inner| Self {
+            inner,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    fn with_canister(&self, canister_id: Principal) -> Option<Self> {
+        self.inner.with_canister(canister_id).map(|inner| Self {
+            inner,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    fn principal(&self) -> Principal {
+        self.inner.principal()
+    }
+
+    fn time(&self) -> SystemTime {
+        self.inner.time()
+    }
+
+    fn time_control(&self) -> Option<&dyn TimeControl> {
+        self.inner.time_control()
+    }
+
+    fn query_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(self.call_raw(false, method, arg))
+    }
+
+    fn update_raw<'a>(
+        &'a self,
+        method: &'a str,
+        arg: Vec<u8>,
+    ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+        Box::pin(self.call_raw(true, method, arg))
+    }
+
+    fn canister_metadata<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> LedgerFuture<'a, Result<Option<Vec<u8>>, LedgerCallError>> {
+        self.inner.canister_metadata(name)
+    }
+
+    fn query<'a, Input, Output>(
+        &'a self,
+        method: &'a str,
+        input: Input,
+    ) -> LedgerFuture<'a, Result<Output, LedgerCallError>>
+    where
+        Input: ArgumentEncoder + std::fmt::Debug,
+        Output: for<'b> ArgumentDecoder<'b> + 'a,
+    {
+        self.call_typed(false, method, input)
+    }
+
+    fn update<'a, Input, Output>(
+        &'a self,
+        method: &'a str,
+        input: Input,
+    ) -> LedgerFuture<'a, Result<Output, LedgerCallError>>
+    where
+        Input: ArgumentEncoder + std::fmt::Debug,
+        Output: for<'b> ArgumentDecoder<'b> + 'a,
+    {
+        self.call_typed(true, method, input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icrc1::{balance_of, transfer};
+    use crate::{Transfer, TransferError};
+    use candid::utils::encode_args;
+    use candid::Nat;
+    use futures::executor::block_on;
+
+    /// Rejects queries to "fail", replies to other queries with a balance,
+    /// and to updates with `InsufficientFunds`.
+    #[derive(Clone)]
+    struct FakeLedger;
+
+    impl LedgerEnv for FakeLedger {
+        fn fork(&self) -> Self {
+            Self
+        }
+
+        fn principal(&self) -> Principal {
+            Principal::anonymous()
+        }
+
+        fn time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+
+        fn query_raw<'a>(
+            &'a self,
+            method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                if method == "fail" {
+                    return Err(LedgerCallError::Reject {
+                        method: method.to_string(),
+                        code: crate::RejectCode::CanisterReject,
+                        message: "the query was rejected".to_string(),
+                    });
+                }
+                Ok(encode_args((Nat::from(42u8),)).unwrap())
+            })
+        }
+
+        fn update_raw<'a>(
+            &'a self,
+            _method: &'a str,
+            _arg: Vec<u8>,
+        ) -> LedgerFuture<'a, Result<Vec<u8>, LedgerCallError>> {
+            Box::pin(async move {
+                let reply: (Result<Nat, TransferError>,) =
+                    (Err(TransferError::InsufficientFunds {
+                        balance: Nat::from(1u8),
+                    }),);
+                Ok(encode_args(reply).unwrap())
+            })
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let ledger = TracingLedger::new(FakeLedger);
+        let fork = ledger.fork();
+        block_on(async {
+            assert_eq!(
+                balance_of(&ledger, Principal::anonymous()).await.unwrap(),
+                42u8
+            );
+            assert_eq!(
+                transfer(&fork, Transfer::amount_to(10u8, Principal::anonymous()))
+                    .await
+                    .unwrap(),
+                Err(TransferError::InsufficientFunds {
+                    balance: Nat::from(1u8)
+                })
+            );
+            let failure: Result<(), LedgerCallError> = ledger.query("fail", ()).await;
+            assert!(failure.is_err());
+        });
+
+        let metrics = ledger.metrics();
+        assert_eq!(
+            metrics.keys().collect::<Vec<_>>(),
+            vec!["fail", "icrc1_balance_of", "icrc1_transfer"]
+        );
+        let outcomes = |method: &str| {
+            metrics[method]
+                .outcomes
+                .iter()
+                .map(|(outcome, count)| (outcome.as_str(), *count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(outcomes("fail"), vec![("reject(CanisterReject)", 1)]);
+        assert_eq!(metrics["fail"].errors, 1);
+        assert_eq!(outcomes("icrc1_balance_of"), vec![("reply", 1)]);
+        assert_eq!(
+            outcomes("icrc1_transfer"),
+            vec![("Err(InsufficientFunds)", 1)]
+        );
+        assert_eq!(metrics["icrc1_transfer"].calls, 1);
+        assert_eq!(metrics["icrc1_transfer"].latency.count(), 1);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        assert_eq!(histogram.mean(), None);
+
+        for ms in [3, 4, 40, 90, 150_000] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.max(), Duration::from_millis(150_000));
+        assert_eq!(histogram.quantile(0.4), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(100)));
+        assert_eq!(
+            histogram.quantile(1.0),
+            Some(Duration::from_millis(150_000))
+        );
+        assert_eq!(
+            histogram
+                .buckets()
+                .filter(|(count, _)| *count > 0)
+                .collect::<Vec<_>>(),
+            vec![
+                (2, Duration::from_millis(5)),
+                (1, Duration::from_millis(50)),
+                (1, Duration::from_millis(100)),
+                (1, Duration::from_millis(150_000)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use candid::{CandidType, Int, Nat};
+use serde::Deserialize;
+
+/// The generic value type defined by the ICRC-3 standard.
+///
+/// The `Blob`, `Text`, `Nat` and `Int` variants coincide with the ICRC-1
+/// metadata value type, so `icrc1_metadata` responses decode into this type
+/// unchanged.
+#[derive(CandidType, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Blob(Vec<u8>),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            Value::Blob(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_nat(&self) -> Option<&Nat> {
+        match self {
+            Value::Nat(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<&Int> {
+        match self {
+            Value::Int(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Map(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    /// Returns the entry with the specified key if this value is a map.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_map()?
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v))
+    }
+
+    /// Follows a dot-separated path of map keys, e.g., `"tx.amt"`.
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(self, |value, key| value.get(key))
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Text(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(blob: Vec<u8>) -> Self {
+        Value::Blob(blob)
+    }
+}
+
+impl From<Nat> for Value {
+    fn from(n: Nat) -> Self {
+        Value::Nat(n)
+    }
+}
+
+impl From<Int> for Value {
+    fn from(i: Int) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+impl From<Vec<(String, Value)>> for Value {
+    fn from(entries: Vec<(String, Value)>) -> Self {
+        Value::Map(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction() -> Value {
+        Value::Map(vec![
+            ("btype".to_string(), Value::from("1xfer")),
+            ("ts".to_string(), Value::Nat(Nat::from(1_000u64))),
+            (
+                "tx".to_string(),
+                Value::Map(vec![
+                    ("amt".to_string(), Value::Nat(Nat::from(10u8))),
+                    ("memo".to_string(), Value::Blob(vec![1, 2, 3])),
+                    (
+                        "from".to_string(),
+                        Value::Array(vec![Value::Blob(vec![4]), Value::Blob(vec![5])]),
+                    ),
+                ]),
+            ),
+            ("delta".to_string(), Value::Int(Int::from(-5))),
+        ])
+    }
+
+    #[test]
+    fn test_map_lookup() {
+        let value = transaction();
+        assert_eq!(value.get("btype"), Some(&Value::from("1xfer")));
+        assert_eq!(value.get("ts"), Some(&Value::Nat(Nat::from(1_000u64))));
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(value.as_map().map(|entries| entries.len()), Some(4));
+        // Only maps have entries.
+        assert_eq!(Value::from("btype").get("btype"), None);
+    }
+
+    #[test]
+    fn test_get_path() {
+        let value = transaction();
+        assert_eq!(value.get_path("tx.amt"), Some(&Value::Nat(Nat::from(10u8))));
+        assert_eq!(value.get_path("btype"), Some(&Value::from("1xfer")));
+        assert_eq!(value.get_path("tx.fee"), None);
+        assert_eq!(value.get_path("fee.amt"), None);
+        // Paths do not descend into values other than maps.
+        assert_eq!(value.get_path("tx.memo.0"), None);
+        assert_eq!(value.get_path("tx.from.0"), None);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let value = transaction();
+        let tx = value.get("tx").unwrap();
+        assert_eq!(
+            tx.get("memo").and_then(Value::as_blob),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(
+            tx.get("from").and_then(Value::as_array),
+            Some(&[Value::Blob(vec![4]), Value::Blob(vec![5])][..])
+        );
+        assert_eq!(value.get("btype").and_then(Value::as_text), Some("1xfer"));
+        assert_eq!(
+            value.get("ts").and_then(Value::as_nat),
+            Some(&Nat::from(1_000u64))
+        );
+        assert_eq!(
+            value.get("delta").and_then(Value::as_int),
+            Some(&Int::from(-5))
+        );
+    }
+
+    #[test]
+    fn test_accessors_type_mismatch() {
+        let text = Value::from("1xfer");
+        assert_eq!(text.as_blob(), None);
+        assert_eq!(text.as_nat(), None);
+        assert_eq!(text.as_int(), None);
+        assert_eq!(text.as_array(), None);
+        assert_eq!(text.as_map(), None);
+
+        // Nat and Int are distinct even for values representable by both.
+        assert_eq!(Value::Nat(Nat::from(5u8)).as_int(), None);
+        assert_eq!(Value::Int(Int::from(5)).as_nat(), None);
+        assert_eq!(Value::Blob(b"1xfer".to_vec()).as_text(), None);
+        assert_eq!(Value::Array(vec![]).as_map(), None);
+        assert_eq!(Value::Map(vec![]).as_array(), None);
+    }
+}
@@ -3,13 +3,15 @@ use candid::{CandidType, Decode, Encode, Nat};
 use ic_agent::Agent;
 use ic_agent::Identity;
 use ic_test_state_machine_client::StateMachine;
+use icrc1_test_env::icrc1::supported_standards;
+use icrc1_test_env::icrc3::{get_blocks, get_tip_certificate};
+use icrc1_test_env::{verify_tip_certificate, GetBlocksRequest};
 use icrc1_test_env_replica::fresh_identity;
 use icrc1_test_env_replica::ReplicaLedger;
 use icrc1_test_env_state_machine::SMLedger;
 use icrc1_test_replica::start_replica;
 use ring::rand::SystemRandom;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 
 const REF_WASM: &[u8] = include_bytes!(env!("REF_WASM_PATH"));
 
@@ -219,13 +221,42 @@ async fn test_state_machine() {
         Some(minter.sender().unwrap()),
     );
 
-    let env = SMLedger::new(Arc::new(sm_env), canister_id, p1.sender().unwrap());
+    let env = SMLedger::new(sm_env, canister_id, p1.sender().unwrap()).with_wasm_metadata(REF_WASM);
 
-    let tests = icrc1_test_suite::test_suite(env).await;
+    let tests = icrc1_test_suite::test_suite(env.clone()).await;
 
     if !icrc1_test_suite::execute_tests(tests).await {
         std::process::exit(1);
     }
+
+    test_tip_certificate(&env).await;
+}
+
+/// Checks that the tip certificate of the ledger is signed by the state
+/// machine and certifies the last block of the log.
+async fn test_tip_certificate(env: &SMLedger) {
+    let standards = supported_standards(env).await.unwrap();
+    if !standards.iter().any(|std| std.name == "ICRC-3") {
+        println!("Skipping the tip certificate test: the ledger does not support ICRC-3");
+        return;
+    }
+
+    let certificate = get_tip_certificate(env)
+        .await
+        .unwrap()
+        .expect("the ledger returned no tip certificate");
+    let tip = verify_tip_certificate(&certificate, env.canister_id(), &env.root_key())
+        .expect("the tip certificate is invalid");
+
+    let log_length = get_blocks(env, vec![GetBlocksRequest::new(0u8, 0u8)])
+        .await
+        .unwrap()
+        .log_length;
+    assert_eq!(
+        Nat::from(tip.last_block_index + 1),
+        log_length,
+        "the tip certificate does not certify the last block"
+    );
 }
 
 #[tokio::main]
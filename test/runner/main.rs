@@ -1,12 +1,15 @@
 use candid::Principal;
 use ic_agent::agent::http_transport::reqwest_transport::ReqwestHttpReplicaV2Transport;
-use ic_agent::identity::BasicIdentity;
 use ic_agent::Agent;
-use icrc1_test_env_replica::ReplicaLedger;
+use icrc1_test_env::IdentitySeed;
+use icrc1_test_env_replica::{KeyType, ReplicaLedger};
+use identity::IdentitySource;
 use pico_args::Arguments;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+mod identity;
+
 fn print_help() {
     println!(
         r#"{} OPTIONS
@@ -16,13 +19,29 @@ Options:
   -c, --canister PRINCIPAL     The canister id of the ledger
 
   -s, --secret-key PATH        The path to the PEM file of the identity
-                               holding enough funds for the test
+                               holding enough funds for the test, with an
+                               Ed25519 or a secp256k1 key
+
+  -i, --identity NAME          The name of the dfx identity holding enough
+                               funds for the test, used instead of
+                               --secret-key
+
+  --seed-phrase-file PATH      The path to a file with the BIP-39 seed phrase
+                               of the identity holding enough funds for the
+                               test, used instead of --secret-key
+
+  --fork-key-type TYPE         The key type of the accounts that the tests
+                               create: ed25519 (default) or secp256k1
+
+  --identity-seed PASSPHRASE   Derive the accounts of each test from this
+                               passphrase and the test name, so that they
+                               are the same in every run
 "#,
         std::env::args().next().unwrap()
     )
 }
 
-#[tokio::main(flavor = "current_thread")]
+#[tokio::main]
 async fn main() {
     let mut args = Arguments::from_env();
     if args.contains(["-h", "--help"]) {
@@ -44,21 +63,65 @@ async fn main() {
         std::process::exit(1);
     });
 
-    let key_path: PathBuf = args
-        .value_from_str(["-s", "--secret-key"])
+    let key_path: Option<PathBuf> = args
+        .opt_value_from_str(["-s", "--secret-key"])
         .unwrap_or_else(|e| {
             eprintln!("Failed to parse secret key path: {}", e);
             print_help();
             std::process::exit(1);
         });
 
-    let identity = BasicIdentity::from_pem_file(&key_path).unwrap_or_else(|e| {
-        panic!(
-            "failed to parse secret key PEM from file {}: {}",
-            key_path.display(),
-            e
-        )
-    });
+    let dfx_identity: Option<String> = args
+        .opt_value_from_str(["-i", "--identity"])
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to parse dfx identity name: {}", e);
+            print_help();
+            std::process::exit(1);
+        });
+
+    let seed_phrase_path: Option<PathBuf> = args
+        .opt_value_from_str("--seed-phrase-file")
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to parse seed phrase path: {}", e);
+            print_help();
+            std::process::exit(1);
+        });
+
+    let identity_source = match (key_path, dfx_identity, seed_phrase_path) {
+        (Some(path), None, None) => IdentitySource::PemFile(path),
+        (None, Some(name), None) => IdentitySource::Dfx(name),
+        (None, None, Some(path)) => IdentitySource::SeedPhraseFile(path),
+        _ => {
+            eprintln!("Specify exactly one of --secret-key, --identity, and --seed-phrase-file");
+            print_help();
+            std::process::exit(1);
+        }
+    };
+
+    let identity = identity_source
+        .load()
+        .unwrap_or_else(|e| panic!("failed to load the identity: {:#}", e));
+
+    let fork_key_type = args
+        .opt_value_from_fn("--fork-key-type", |s: &str| match s {
+            "ed25519" => Ok(KeyType::Ed25519),
+            "secp256k1" => Ok(KeyType::Secp256k1),
+            _ => Err(format!("unknown key type {}", s)),
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to parse fork key type: {}", e);
+            print_help();
+            std::process::exit(1);
+        })
+        .unwrap_or_default();
+
+    let identity_seed: Option<String> =
+        args.opt_value_from_str("--identity-seed")
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to parse identity seed: {}", e);
+                print_help();
+                std::process::exit(1);
+            });
 
     let client = reqwest::ClientBuilder::new()
         .build()
@@ -80,8 +143,25 @@ async fn main() {
         .await
         .expect("agent failed to fetch the root key");
 
-    let env = ReplicaLedger::new(agent, canister_id);
-    let tests = icrc1_test_suite::test_suite(env).await;
+    let mut env = ReplicaLedger::new(agent, canister_id).with_key_type(fork_key_type);
+    if let Some(passphrase) = identity_seed {
+        env = env.with_identity_seed(IdentitySeed::from_master(passphrase.as_bytes()));
+    }
+    // Run each test in its own task, so that the tests use all cores.
+    // Exclusive tests must not run concurrently, so they are left to
+    // `execute_tests`.
+    let tests = icrc1_test_suite::test_suite(env)
+        .await
+        .into_iter()
+        .map(|test| {
+            if test.is_exclusive() {
+                return test;
+            }
+            let name = test.name().to_string();
+            let handle = tokio::spawn(test.run());
+            icrc1_test_suite::test(name, async move { handle.await? })
+        })
+        .collect();
 
     if !icrc1_test_suite::execute_tests(tests).await {
         std::process::exit(1);
@@ -1,18 +1,24 @@
 use anyhow::{bail, Context};
-use candid::Nat;
+use candid::utils::encode_args;
+use candid::{CandidType, Nat, Principal};
 use futures::StreamExt;
 use icrc1_test_env::icrc1::{
-    balance_of, metadata, minting_account, supported_standards, token_decimals, token_name,
+    balance_of, minting_account, supported_standards, token_decimals, token_metadata, token_name,
     token_symbol, transfer, transfer_fee,
 };
 use icrc1_test_env::icrc2::{allowance, approve, transfer_from};
 use icrc1_test_env::ApproveArgs;
 use icrc1_test_env::TransferFromArgs;
-use icrc1_test_env::{Account, LedgerEnv, Transfer, TransferError, Value};
+use icrc1_test_env::{
+    decode_call_reply, LedgerCallError, LedgerFuture, MaybeSend, RejectCode, TimeControl,
+};
+use icrc1_test_env::{
+    fund_subaccounts, with_supply_snapshots, Account, CandidService, InjectedFault, LedgerEnv,
+    SupplyChange, TokenAmount, Transfer, TransferError,
+};
 use icrc1_test_env::{AllowanceArgs, ApproveError, TransferFromError};
 use std::future::Future;
-use std::pin::Pin;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 pub enum Outcome {
     Passed,
@@ -21,24 +27,91 @@ pub enum Outcome {
 
 pub type TestResult = anyhow::Result<Outcome>;
 
+/// The longest transaction window the tests wait for, see [icrc1_test_tx_window].
+const MAX_TX_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The longest memo limit [icrc1_test_oversized_memo] exceeds. Longer memos
+/// do not fit into an ingress message.
+const MAX_CHECKED_MEMO_LENGTH: u64 = 1024 * 1024;
+
+/// The Candid interfaces of the standards, see [icrc1_test_candid_interface].
+/// The crate keeps copies of the `.did` files so that it builds when
+/// published on its own.
+const STANDARD_INTERFACES: &[(&str, &str)] = &[
+    ("ICRC-1", include_str!("standards/ICRC-1.did")),
+    ("ICRC-2", include_str!("standards/ICRC-2.did")),
+    ("ICRC-3", include_str!("standards/ICRC-3.did")),
+];
+
 pub struct Test {
     name: String,
-    action: Pin<Box<dyn Future<Output = TestResult>>>,
+    action: LedgerFuture<'static, TestResult>,
+    exclusive: bool,
 }
 
-pub fn test(name: impl Into<String>, body: impl Future<Output = TestResult> + 'static) -> Test {
+impl Test {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns true if the test must not run concurrently with other tests,
+    /// see [exclusive_test].
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Runs the test and returns its outcome.
+    pub async fn run(self) -> TestResult {
+        self.action.await
+    }
+}
+
+pub fn test(
+    name: impl Into<String>,
+    body: impl Future<Output = TestResult> + MaybeSend + 'static,
+) -> Test {
     Test {
         name: name.into(),
         action: Box::pin(body),
+        exclusive: false,
+    }
+}
+
+/// Creates a test that must not run concurrently with other tests, for
+/// example because it moves the ledger time.
+pub fn exclusive_test(
+    name: impl Into<String>,
+    body: impl Future<Output = TestResult> + MaybeSend + 'static,
+) -> Test {
+    Test {
+        exclusive: true,
+        ..test(name, body)
     }
 }
 
-fn lookup<'a, K, V, U>(meta: &'a [(K, V)], key: &U) -> Option<&'a V>
+/// Returns the environment of the test with the specified name, so that
+/// seeded environments fork the same callers for the test in every run, see
+/// [LedgerEnv::with_label].
+fn labeled<E: LedgerEnv + Clone>(env: &E, name: &str) -> E {
+    env.with_label(name).unwrap_or_else(|| env.clone())
+}
+
+fn ledger_test<E, F, Fut>(env: &E, name: &str, body: F) -> Test
 where
-    K: PartialEq<U>,
-    U: ?Sized,
+    E: LedgerEnv + Clone,
+    F: FnOnce(E) -> Fut,
+    Fut: Future<Output = TestResult> + MaybeSend + 'static,
 {
-    meta.iter().find_map(|(k, v)| (k == key).then_some(v))
+    test(name, body(labeled(env, name)))
+}
+
+fn exclusive_ledger_test<E, F, Fut>(env: &E, name: &str, body: F) -> Test
+where
+    E: LedgerEnv + Clone,
+    F: FnOnce(E) -> Fut,
+    Fut: Future<Output = TestResult> + MaybeSend + 'static,
+{
+    exclusive_test(name, body(labeled(env, name)))
 }
 
 fn assert_equal<T: PartialEq + std::fmt::Debug>(lhs: T, rhs: T) -> anyhow::Result<()> {
@@ -76,13 +149,97 @@ async fn assert_balance(
         bail!(
             "Expected the balance of account {:?} to be {}, got {}",
             account,
-            expected,
-            actual
+            display_amount(ledger, expected).await,
+            display_amount(ledger, actual).await
         )
     }
     Ok(())
 }
 
+/// Returns the amount of tokens most tests transfer: 0.0001 tokens, or the
+/// smallest amount if the token has fewer than four decimals.
+async fn test_amount(ledger: &impl LedgerEnv) -> anyhow::Result<TokenAmount> {
+    let decimals = token_decimals(ledger).await?;
+    Ok(TokenAmount::parse("0.0001", decimals)
+        .unwrap_or_else(|_| TokenAmount::from_base_units(1u8, decimals)))
+}
+
+/// Formats an amount of base units in tokens for the messages of failed
+/// checks. Falls back to base units if the ledger does not report its
+/// decimals.
+async fn display_amount(ledger: &impl LedgerEnv, base_units: Nat) -> String {
+    match token_decimals(ledger).await {
+        Ok(decimals) => TokenAmount::from_base_units(base_units, decimals).to_string(),
+        Err(_) => format!("{} base units", base_units),
+    }
+}
+
+/// The balances of the accounts of a test before the test moved any funds.
+///
+/// Seeded environments fork the same accounts in every run, so an account
+/// may hold funds left over by an earlier run. The tests check balances
+/// relative to the starting balances instead of absolute balances.
+#[derive(Default)]
+struct Balances {
+    start: Vec<(Account, Nat)>,
+}
+
+impl Balances {
+    /// Records the current balance of an account as its starting balance.
+    async fn record(
+        &mut self,
+        ledger: &impl LedgerEnv,
+        account: impl Into<Account>,
+    ) -> anyhow::Result<()> {
+        let account = account.into();
+        let balance = balance_of(ledger, account.clone()).await?;
+        self.start.push((account, balance));
+        Ok(())
+    }
+
+    /// Records the starting balance of an account that was just funded
+    /// with `funded` tokens.
+    async fn record_funded(
+        &mut self,
+        ledger: &impl LedgerEnv,
+        account: impl Into<Account>,
+        funded: impl Into<Nat>,
+    ) -> anyhow::Result<()> {
+        let account = account.into();
+        let balance = balance_of(ledger, account.clone()).await?;
+        self.start.push((account, balance - funded.into()));
+        Ok(())
+    }
+
+    /// Returns the starting balance of an account plus `change`.
+    fn expected(&self, account: &Account, change: impl Into<Nat>) -> anyhow::Result<Nat> {
+        let start = self
+            .start
+            .iter()
+            .find(|(recorded, _)| {
+                recorded.owner == account.owner
+                    && recorded.subaccount.unwrap_or_default()
+                        == account.subaccount.unwrap_or_default()
+            })
+            .map(|(_, balance)| balance.clone())
+            .with_context(|| format!("no starting balance recorded for {:?}", account))?;
+        Ok(start + change.into())
+    }
+
+    /// Checks that the balance of an account is its starting balance plus
+    /// `change`.
+    async fn assert(
+        &self,
+        ledger: &impl LedgerEnv,
+        account: impl Into<Account>,
+        change: impl Into<Nat>,
+    ) -> anyhow::Result<()> {
+        let account = account.into();
+        let expected = self.expected(&account, change)?;
+        assert_balance(ledger, account, expected).await
+    }
+}
+
 async fn assert_allowance(
     ledger_env: &impl LedgerEnv,
     from: impl Into<Account>,
@@ -106,8 +263,8 @@ async fn assert_allowance(
             "Expected the {:?} -> {:?} allowance to be {}, got {}",
             from,
             spender,
-            expected_allowance,
-            allowance.allowance
+            display_amount(ledger_env, expected_allowance).await,
+            display_amount(ledger_env, allowance.allowance).await
         );
     }
     if allowance.expires_at != expires_at {
@@ -116,35 +273,56 @@ async fn assert_allowance(
     Ok(())
 }
 
-async fn setup_test_account(
-    ledger_env: &impl LedgerEnv,
+/// Returns the control of the ledger time, or the outcome of a test that
+/// needs it if the environment cannot move the ledger time.
+fn time_control(ledger_env: &impl LedgerEnv) -> Result<&dyn TimeControl, Outcome> {
+    ledger_env.time_control().ok_or_else(|| Outcome::Skipped {
+        reason: "the environment cannot move the ledger time".to_string(),
+    })
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos() as u64
+}
+
+/// Forks an environment and records the starting balance of its caller.
+async fn fork_account<L: LedgerEnv>(ledger_env: &L, balances: &mut Balances) -> anyhow::Result<L> {
+    let env = ledger_env.fork();
+    balances.record(&env, env.principal()).await?;
+    Ok(env)
+}
+
+async fn setup_test_account<L: LedgerEnv>(
+    ledger_env: &L,
     amount: Nat,
-) -> anyhow::Result<impl LedgerEnv> {
+    balances: &mut Balances,
+) -> anyhow::Result<L> {
     let balance = balance_of(ledger_env, ledger_env.principal()).await?;
     assert!(balance >= amount.clone() + transfer_fee(ledger_env).await?);
-    let receiver_env = ledger_env.fork();
+    let receiver_env = fork_account(ledger_env, balances).await?;
     let receiver = receiver_env.principal();
-    assert_balance(&receiver_env, receiver, 0u8).await?;
     let _tx = transfer(ledger_env, Transfer::amount_to(amount.clone(), receiver)).await??;
-    assert_balance(
-        &receiver_env,
-        Account {
-            owner: receiver,
-            subaccount: None,
-        },
-        amount.clone(),
-    )
-    .await?;
+    balances
+        .assert(
+            &receiver_env,
+            Account {
+                owner: receiver,
+                subaccount: None,
+            },
+            amount.clone(),
+        )
+        .await?;
     Ok(receiver_env)
 }
 
 /// Checks whether the ledger supports token transfers and handles
 /// default sub accounts correctly.
 pub async fn icrc1_test_transfer(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
-    let transfer_amount = Nat::from(10_000u16);
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
-    let p1_env = setup_test_account(&ledger_env, initial_balance).await?;
+    let p1_env = setup_test_account(&ledger_env, initial_balance, &mut balances).await?;
     let p2_env = ledger_env.fork();
 
     let balance_p1 = balance_of(&p1_env, p1_env.principal()).await?;
@@ -189,6 +367,7 @@ pub async fn icrc1_test_transfer(ledger_env: impl LedgerEnv) -> TestResult {
 /// Checks whether the ledger supports token burns.
 /// Skips the checks if the ledger does not have a minting account.
 pub async fn icrc1_test_burn(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
     let minting_account = match minting_account(&ledger_env).await? {
         Some(account) => account,
         None => {
@@ -202,13 +381,14 @@ pub async fn icrc1_test_burn(ledger_env: impl LedgerEnv) -> TestResult {
         .await
         .context("minting account cannot hold any funds")?;
 
-    let burn_amount = Nat::from(10_000u16);
-    let p1_env = setup_test_account(&ledger_env, burn_amount.clone()).await?;
+    let burn_amount = test_amount(&ledger_env).await?;
+    let p1_env =
+        setup_test_account(&ledger_env, burn_amount.base_units().clone(), &mut balances).await?;
 
     // Burning tokens is done by sending the burned amount to the minting account
     let _tx = transfer(
         &p1_env,
-        Transfer::amount_to(burn_amount.clone(), minting_account.clone()),
+        Transfer::amount_to(burn_amount.base_units().clone(), minting_account.clone()),
     )
     .await?
     .with_context(|| {
@@ -219,38 +399,126 @@ pub async fn icrc1_test_burn(ledger_env: impl LedgerEnv) -> TestResult {
         )
     })?;
 
-    assert_balance(&p1_env, p1_env.principal(), 0u8).await?;
+    balances.assert(&p1_env, p1_env.principal(), 0u8).await?;
     assert_balance(&ledger_env, minting_account, 0u8).await?;
 
     Ok(Outcome::Passed)
 }
 
-/// Checks whether the ledger metadata entries agree with named methods.
-pub async fn icrc1_test_metadata(ledger: impl LedgerEnv) -> TestResult {
-    let mut metadata = metadata(&ledger).await?;
-    metadata.sort_by(|l, r| l.0.cmp(&r.0));
+/// Checks that an operation minted and burned the expected amounts and did
+/// not change the balance of the minting account.
+async fn assert_supply_change(
+    ledger: &impl LedgerEnv,
+    operation: &str,
+    change: &SupplyChange,
+    minted: Nat,
+    burned: Nat,
+) -> anyhow::Result<()> {
+    if change.before.minting_account_balance != change.after.minting_account_balance {
+        bail!(
+            "{} changed the balance of the minting account from {} to {}",
+            operation,
+            display_amount(ledger, change.before.minting_account_balance.clone()).await,
+            display_amount(ledger, change.after.minting_account_balance.clone()).await
+        );
+    }
+    if change.minted() != minted || change.burned() != burned {
+        bail!(
+            "{} changed the total supply from {} to {}, expected {} minted and {} burned",
+            operation,
+            display_amount(ledger, change.before.total_supply.clone()).await,
+            display_amount(ledger, change.after.total_supply.clone()).await,
+            display_amount(ledger, minted).await,
+            display_amount(ledger, burned).await
+        );
+    }
+    Ok(())
+}
 
-    for ((k1, _), (k2, _)) in metadata.iter().zip(metadata.iter().skip(1)) {
-        if k1 == k2 {
-            bail!("Key {} is duplicated in the metadata", k1);
+/// Checks that transfers, burns, and mints change the total supply by the
+/// burned fee, the burned amount, and the minted amount. Checks mints only
+/// if the caller is the minting account, which then funds the test account
+/// with a mint. Runs exclusively, since the other tests change the total
+/// supply.
+pub async fn icrc1_test_supply(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let minting_account = match minting_account(&ledger_env).await? {
+        Some(account) => account,
+        None => {
+            return Ok(Outcome::Skipped {
+                reason: "the ledger does not have a minting account".to_string(),
+            });
         }
-    }
+    };
+    let fee = transfer_fee(&ledger_env).await?;
+    let amount = test_amount(&ledger_env).await?.into_base_units();
+    let funding = amount.clone() * 2u8 + fee.clone();
+    let p1_env = if minting_account.owner == ledger_env.principal()
+        && minting_account.subaccount.unwrap_or_default() == [0; 32]
+    {
+        let p1_env = fork_account(&ledger_env, &mut balances).await?;
+        let (tx, change) = with_supply_snapshots(&ledger_env, || {
+            transfer(
+                &ledger_env,
+                Transfer::amount_to(funding.clone(), p1_env.principal()),
+            )
+        })
+        .await?;
+        tx?.context("failed to mint tokens")?;
+        assert_supply_change(&ledger_env, "a mint", &change, funding, 0u8.into()).await?;
+        p1_env
+    } else {
+        setup_test_account(&ledger_env, funding, &mut balances).await?
+    };
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
+
+    let (tx, change) = with_supply_snapshots(&ledger_env, || {
+        transfer(
+            &p1_env,
+            Transfer::amount_to(amount.clone(), p2_env.principal()),
+        )
+    })
+    .await?;
+    tx?.context("failed to transfer between test accounts")?;
+    // Ledgers with a fee collector do not burn the fees.
+    let burned_fee = if change.burned() == 0u8 {
+        Nat::from(0u8)
+    } else {
+        fee
+    };
+    assert_supply_change(&ledger_env, "a transfer", &change, 0u8.into(), burned_fee).await?;
 
-    if let Some(name) = lookup(&metadata, "icrc1:name") {
-        assert_equal(&Value::Text(token_name(&ledger).await?), name)
+    let (tx, change) = with_supply_snapshots(&ledger_env, || {
+        transfer(
+            &p1_env,
+            Transfer::amount_to(amount.clone(), minting_account.clone()),
+        )
+    })
+    .await?;
+    tx?.context("failed to burn tokens")?;
+    assert_supply_change(&ledger_env, "a burn", &change, 0u8.into(), amount).await?;
+
+    Ok(Outcome::Passed)
+}
+
+/// Checks whether the ledger metadata entries agree with named methods.
+pub async fn icrc1_test_metadata(ledger: impl LedgerEnv) -> TestResult {
+    let metadata = token_metadata(&ledger).await?;
+
+    if let Some(name) = metadata.name {
+        assert_equal(token_name(&ledger).await?, name)
             .context("icrc1:name metadata entry does not match the icrc1_name endpoint")?;
     }
-    if let Some(sym) = lookup(&metadata, "icrc1:symbol") {
-        assert_equal(&Value::Text(token_symbol(&ledger).await?), sym)
+    if let Some(sym) = metadata.symbol {
+        assert_equal(token_symbol(&ledger).await?, sym)
             .context("icrc1:symol metadata entry does not match the icrc1_symbol endpoint")?;
     }
-    if let Some(meta_decimals) = lookup(&metadata, "icrc1:decimals") {
-        let decimals = token_decimals(&ledger).await?;
-        assert_equal(&Value::Nat(Nat::from(decimals)), meta_decimals)
+    if let Some(meta_decimals) = metadata.decimals {
+        assert_equal(token_decimals(&ledger).await?, meta_decimals)
             .context("icrc1:decimals metadata entry does not match the icrc1_decimals endpoint")?;
     }
-    if let Some(fee) = lookup(&metadata, "icrc1:fee") {
-        assert_equal(&Value::Nat(transfer_fee(&ledger).await?), fee)
+    if let Some(fee) = metadata.fee {
+        assert_equal(transfer_fee(&ledger).await?, fee)
             .context("icrc1:fee metadata entry does not match the icrc1_fee endpoint")?;
     }
     Ok(Outcome::Passed)
@@ -266,6 +534,62 @@ pub async fn icrc1_test_supported_standards(ledger: impl LedgerEnv) -> anyhow::R
     Ok(Outcome::Passed)
 }
 
+/// Checks the ledger interface against the interface of each standard in
+/// `standards`.
+fn check_candid_interface(service: &CandidService, standards: &[String]) -> anyhow::Result<()> {
+    let mut mismatches = vec![];
+    for (name, standard_did) in STANDARD_INTERFACES {
+        if !standards.iter().any(|std| std == name) {
+            continue;
+        }
+        let standard = CandidService::parse(standard_did)
+            .with_context(|| format!("failed to parse the {} interface", name))?;
+        mismatches.extend(
+            service
+                .mismatches(&standard)
+                .into_iter()
+                .map(|mismatch| format!("{}: {}", name, mismatch)),
+        );
+    }
+    if !mismatches.is_empty() {
+        bail!(
+            "the ledger interface does not conform to the supported standards:\n{}",
+            mismatches.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the Candid interface of the ledger, its `candid:service`
+/// metadata, conforms to the interface of each supported standard.
+/// Skips the checks if the metadata is not a Candid service description
+/// the parser understands, e.g., because it uses newer Candid features.
+pub async fn icrc1_test_candid_interface(ledger_env: impl LedgerEnv) -> TestResult {
+    let did = match ledger_env.canister_metadata("candid:service").await? {
+        Some(did) => String::from_utf8(did).context("the candid:service metadata is not UTF-8")?,
+        None => {
+            return Ok(Outcome::Skipped {
+                reason: "the ledger has no candid:service metadata".to_string(),
+            })
+        }
+    };
+    let standards: Vec<_> = supported_standards(&ledger_env)
+        .await?
+        .into_iter()
+        .map(|std| std.name)
+        .collect();
+    let service = match CandidService::parse(&did) {
+        Ok(service) => service,
+        Err(err) => {
+            return Ok(Outcome::Skipped {
+                reason: format!("failed to parse the candid:service metadata: {}", err),
+            })
+        }
+    };
+    check_candid_interface(&service, &standards)?;
+    Ok(Outcome::Passed)
+}
+
 /// Checks whether the ledger advertizes support for ICRC-2 standard.
 pub async fn icrc2_test_supported_standards(ledger: impl LedgerEnv) -> anyhow::Result<Outcome> {
     let stds = supported_standards(&ledger).await?;
@@ -283,14 +607,26 @@ pub async fn icrc2_test_supported_standards(ledger: impl LedgerEnv) -> anyhow::R
 
 /// Checks basic functionality of the ICRC-2 approve endpoint.
 pub async fn icrc2_test_approve(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let initial_balance: Nat = fee.clone() * 2u8;
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
     let p2_subaccount = Account {
         owner: p2_env.principal(),
         subaccount: Some([1; 32]),
     };
+    balances.record(&ledger_env, p2_subaccount.clone()).await?;
+    // An earlier run with the same seeded accounts may have left an
+    // allowance for the subaccount.
+    let p2_subaccount_allowance = allowance(
+        &p1_env,
+        AllowanceArgs {
+            account: p1_env.principal().into(),
+            spender: p2_subaccount.clone(),
+        },
+    )
+    .await?;
     let approve_amount = fee.clone();
 
     approve(
@@ -312,14 +648,20 @@ pub async fn icrc2_test_approve(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
         &p1_env,
         p1_env.principal(),
         p2_subaccount.clone(),
-        0u8,
-        None,
+        p2_subaccount_allowance.allowance,
+        p2_subaccount_allowance.expires_at,
     )
     .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), fee.clone()).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p2_subaccount.clone(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), fee.clone())
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_subaccount.clone(), 0u8)
+        .await?;
 
     // Approval for a subaccount.
     approve(
@@ -346,9 +688,13 @@ pub async fn icrc2_test_approve(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
     )
     .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p2_subaccount, 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
+    balances.assert(&ledger_env, p2_subaccount, 0u8).await?;
 
     // Insufficient funds to pay the fee for a second approval
     match approve(
@@ -360,8 +706,13 @@ pub async fn icrc2_test_approve(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
         Ok(_) => bail!("expected ApproveError::InsufficientFunds, got Ok result"),
         Err(e) => match e {
             ApproveError::InsufficientFunds { balance } => {
-                if balance != 0u8 {
-                    bail!("wrong balance, expected 0, got: {}", balance);
+                let expected = balances.expected(&p1_env.principal().into(), 0u8)?;
+                if balance != expected {
+                    bail!(
+                        "wrong balance, expected {}, got: {}",
+                        display_amount(&p1_env, expected).await,
+                        display_amount(&p1_env, balance).await
+                    );
                 }
             }
             _ => return Err(e).context("expected ApproveError::InsufficientFunds"),
@@ -373,12 +724,23 @@ pub async fn icrc2_test_approve(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
 
 /// Checks the ICRC-2 approve endpoint for correct handling of the expiration functionality.
 pub async fn icrc2_test_approve_expiration(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let initial_balance: Nat = fee.clone() * 2u8;
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
     let approve_amount = fee.clone();
     let now = time_nanos(&ledger_env);
+    // An earlier run with the same seeded accounts may have left an
+    // allowance.
+    let start_allowance = allowance(
+        &p1_env,
+        AllowanceArgs {
+            account: p1_env.principal().into(),
+            spender: p2_env.principal().into(),
+        },
+    )
+    .await?;
 
     // Expiration in the past
     match approve(
@@ -394,10 +756,21 @@ pub async fn icrc2_test_approve_expiration(ledger_env: impl LedgerEnv) -> anyhow
         },
     }
 
-    assert_allowance(&p1_env, p1_env.principal(), p2_env.principal(), 0u8, None).await?;
+    assert_allowance(
+        &p1_env,
+        p1_env.principal(),
+        p2_env.principal(),
+        start_allowance.allowance,
+        start_allowance.expires_at,
+    )
+    .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), initial_balance.clone()).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), initial_balance.clone())
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
 
     // Correct expiration in the future
     let expiration = u64::MAX;
@@ -417,8 +790,12 @@ pub async fn icrc2_test_approve_expiration(ledger_env: impl LedgerEnv) -> anyhow
     )
     .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), fee).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), fee)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
 
     // Change expiration
     let new_expiration = expiration - 1;
@@ -438,20 +815,94 @@ pub async fn icrc2_test_approve_expiration(ledger_env: impl LedgerEnv) -> anyhow
     )
     .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
 
     Ok(Outcome::Passed)
 }
 
+/// Checks that an approval expires when the ledger time passes its
+/// expiration. Moves the ledger time.
+pub async fn icrc2_test_approval_expiry(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let clock = match time_control(&ledger_env) {
+        Ok(clock) => clock,
+        Err(outcome) => return Ok(outcome),
+    };
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = fee.clone();
+    // Fund the approval and two transfers.
+    let approve_amount: Nat = (transfer_amount.clone() + fee.clone()) * 2u8;
+    let initial_balance: Nat = approve_amount.clone() + fee.clone();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
+    let p3_env = fork_account(&ledger_env, &mut balances).await?;
+
+    let lifetime = Duration::from_secs(60 * 60);
+    let expires_at = time_nanos(&p1_env) + nanos(lifetime);
+    approve(
+        &p1_env,
+        ApproveArgs::approve_amount(approve_amount.clone(), p2_env.principal())
+            .expires_at(expires_at),
+    )
+    .await??;
+
+    let transfer_args = TransferFromArgs::transfer_from(
+        transfer_amount.clone(),
+        p3_env.principal(),
+        p1_env.principal(),
+    );
+    transfer_from(&p2_env, transfer_args.clone())
+        .await?
+        .context("Expected transfer_from to succeed before the approval expired")?;
+    assert_allowance(
+        &p1_env,
+        p1_env.principal(),
+        p2_env.principal(),
+        transfer_amount.clone() + fee.clone(),
+        Some(expires_at),
+    )
+    .await?;
+
+    clock.advance_time(lifetime * 2);
+
+    assert_allowance(&p1_env, p1_env.principal(), p2_env.principal(), 0u8, None).await?;
+    match transfer_from(&p2_env, transfer_args).await? {
+        Err(TransferFromError::InsufficientAllowance { allowance }) => {
+            assert_equal(allowance, Nat::from(0u8))?
+        }
+        other => bail!(
+            "Expected InsufficientAllowance after the approval expired, got {:?}",
+            other
+        ),
+    }
+
+    balances
+        .assert(
+            &ledger_env,
+            p1_env.principal(),
+            initial_balance - fee.clone() * 2u8 - transfer_amount.clone(),
+        )
+        .await?;
+    balances
+        .assert(&ledger_env, p3_env.principal(), transfer_amount)
+        .await?;
+    Ok(Outcome::Passed)
+}
+
 /// Checks the ICRC-2 approve endpoint for correct handling of the expected allowance functionality.
 pub async fn icrc2_test_approve_expected_allowance(
     ledger_env: impl LedgerEnv,
 ) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let initial_balance: Nat = fee.clone() * 2u8;
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
     let approve_amount = fee.clone();
 
     approve(
@@ -501,21 +952,26 @@ pub async fn icrc2_test_approve_expected_allowance(
     )
     .await?;
 
-    assert_balance(&ledger_env, p1_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
 
     Ok(Outcome::Passed)
 }
 
 /// Checks the basic functionality of the ICRC-2 transfer from endpoint.
 pub async fn icrc2_test_transfer_from(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     // Charge account with some tokens plus two times the transfer fee, once for approving and once for transferring
     let transfer_amount = fee.clone();
     let initial_balance: Nat = transfer_amount.clone() * 2u8 + fee.clone() * 2u8;
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
-    let p3_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
+    let p3_env = fork_account(&ledger_env, &mut balances).await?;
 
     // Approve amount needs to be the transferred amount + the fee for transferring
     let approve_amount: Nat = transfer_amount.clone() + fee.clone();
@@ -538,17 +994,22 @@ pub async fn icrc2_test_transfer_from(ledger_env: impl LedgerEnv) -> anyhow::Res
     )
     .await??;
 
-    assert_balance(
-        &ledger_env,
-        p1_env.principal(),
-        // Balance should be the initial balance minus two times the fee, once for the approve and once for the transfer, and the transferred amount
-        initial_balance - fee.clone() - fee - transfer_amount.clone(),
-    )
-    .await?;
+    balances
+        .assert(
+            &ledger_env,
+            p1_env.principal(),
+            // Balance should be the initial balance minus two times the fee, once for the approve and once for the transfer, and the transferred amount
+            initial_balance - fee.clone() - fee - transfer_amount.clone(),
+        )
+        .await?;
     // Balance of spender should not change
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
     // Beneficiary should get the amount transferred
-    assert_balance(&ledger_env, p3_env.principal(), transfer_amount).await?;
+    balances
+        .assert(&ledger_env, p3_env.principal(), transfer_amount)
+        .await?;
 
     assert_allowance(
         &p1_env,
@@ -565,16 +1026,20 @@ pub async fn icrc2_test_transfer_from(ledger_env: impl LedgerEnv) -> anyhow::Res
 pub async fn icrc2_test_transfer_from_insufficient_funds(
     ledger_env: impl LedgerEnv,
 ) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let transfer_amount = fee.clone();
     // The initial balance is not enough to cover the fee for approval and transfer_from.
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
-    let p3_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
+    let p3_env = fork_account(&ledger_env, &mut balances).await?;
+    // Moving the whole balance left after the approval fee leaves nothing for the transfer
+    // fee, however much the account held before the test.
+    let available = balances.expected(&p1_env.principal().into(), transfer_amount.clone())?;
 
     // Approve sufficient amount.
-    let approve_amount: Nat = transfer_amount.clone() + fee.clone();
+    let approve_amount: Nat = available.clone() + fee.clone();
     approve(
         &p1_env,
         ApproveArgs::approve_amount(approve_amount.clone(), p2_env.principal()),
@@ -583,22 +1048,18 @@ pub async fn icrc2_test_transfer_from_insufficient_funds(
 
     match transfer_from(
         &p2_env,
-        TransferFromArgs::transfer_from(
-            transfer_amount.clone(),
-            p3_env.principal(),
-            p1_env.principal(),
-        ),
+        TransferFromArgs::transfer_from(available.clone(), p3_env.principal(), p1_env.principal()),
     )
     .await?
     {
         Ok(_) => bail!("expected TransferFromError::InsufficientFunds, got Ok result"),
         Err(e) => match e {
             TransferFromError::InsufficientFunds { balance } => {
-                if balance != transfer_amount {
+                if balance != available {
                     bail!(
                         "wrong balance, expected {}, got: {}",
-                        transfer_amount,
-                        balance
+                        display_amount(&p2_env, available).await,
+                        display_amount(&p2_env, balance).await
                     );
                 }
             }
@@ -607,9 +1068,15 @@ pub async fn icrc2_test_transfer_from_insufficient_funds(
     }
 
     // p1_env balance was reduced by the approval fee.
-    assert_balance(&ledger_env, p1_env.principal(), transfer_amount).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p3_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), transfer_amount)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p3_env.principal(), 0u8)
+        .await?;
 
     // Allowance is not changed.
     assert_allowance(
@@ -628,12 +1095,13 @@ pub async fn icrc2_test_transfer_from_insufficient_funds(
 pub async fn icrc2_test_transfer_from_insufficient_allowance(
     ledger_env: impl LedgerEnv,
 ) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let transfer_amount = fee.clone();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
-    let p3_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
+    let p3_env = fork_account(&ledger_env, &mut balances).await?;
 
     match transfer_from(
         &p2_env,
@@ -657,20 +1125,27 @@ pub async fn icrc2_test_transfer_from_insufficient_allowance(
     }
 
     // Balances are not changed.
-    assert_balance(&ledger_env, p1_env.principal(), initial_balance).await?;
-    assert_balance(&ledger_env, p2_env.principal(), 0u8).await?;
-    assert_balance(&ledger_env, p3_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), initial_balance)
+        .await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), 0u8)
+        .await?;
+    balances
+        .assert(&ledger_env, p3_env.principal(), 0u8)
+        .await?;
 
     Ok(Outcome::Passed)
 }
 
 /// Checks the ICRC-2 transfer from endpoint for correct handling of self transfers.
 pub async fn icrc2_test_transfer_from_self(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
     let transfer_amount = fee.clone();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = ledger_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&ledger_env, &mut balances).await?;
 
     // icrc2_transfer_from does not require approval if spender == from
     transfer_from(
@@ -684,21 +1159,26 @@ pub async fn icrc2_test_transfer_from_self(ledger_env: impl LedgerEnv) -> anyhow
     .await??;
 
     // Transferred the transfer_amount and paid fee; the balance is now 0.
-    assert_balance(&ledger_env, p1_env.principal(), 0u8).await?;
+    balances
+        .assert(&ledger_env, p1_env.principal(), 0u8)
+        .await?;
     // Beneficiary should get the amount transferred.
-    assert_balance(&ledger_env, p2_env.principal(), transfer_amount).await?;
+    balances
+        .assert(&ledger_env, p2_env.principal(), transfer_amount)
+        .await?;
 
     Ok(Outcome::Passed)
 }
 
 /// Checks whether the ledger applies deduplication of transactions correctly
 pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
-    let transfer_amount = Nat::from(10_000u64);
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
     let initial_balance: Nat = transfer_amount.clone() * 7u8 + fee.clone() * 7u8;
     // Create two test accounts and transfer some tokens to the first account. Also charge them with enough tokens so they can pay the transfer fees
-    let p1_env = setup_test_account(&ledger_env, initial_balance.clone()).await?;
-    let p2_env = p1_env.fork();
+    let p1_env = setup_test_account(&ledger_env, initial_balance.clone(), &mut balances).await?;
+    let p2_env = fork_account(&p1_env, &mut balances).await?;
 
     // Deduplication should not happen if the created_at_time field is unset.
     let transfer_args = Transfer::amount_to(transfer_amount.clone(), p2_env.principal());
@@ -706,13 +1186,17 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         .await?
         .context("failed to execute the first no-dedup transfer")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone()).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone())
+        .await?;
 
     transfer(&p1_env, transfer_args.clone())
         .await?
         .context("failed to execute the second no-dedup transfer")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 2u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 2u8)
+        .await?;
 
     // Setting the created_at_time field changes the transaction
     // identity, so the transfer should succeed.
@@ -728,7 +1212,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         Err(e) => return Err(e).context("failed to execute the first dedup transfer"),
     };
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 3u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 3u8)
+        .await?;
 
     // Sending the same transfer again should trigger deduplication.
     assert_equal(
@@ -738,7 +1224,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         transfer(&p1_env, transfer_args.clone()).await?,
     )?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 3u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 3u8)
+        .await?;
 
     // Explicitly setting the fee field changes the transaction
     // identity, so the transfer should succeed.
@@ -748,7 +1236,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         .await?
         .context("failed to execute the transfer with an explicitly set fee field")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 4u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 4u8)
+        .await?;
 
     assert_not_equal(&txid, &txid_2).context("duplicate txid")?;
 
@@ -760,7 +1250,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         transfer(&p1_env, transfer_args.clone()).await?,
     )?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 4u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 4u8)
+        .await?;
 
     // A custom memo changes the transaction identity, so the transfer
     // should succeed.
@@ -770,7 +1262,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         .await?
         .context("failed to execute the transfer with an explicitly set memo field")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 5u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 5u8)
+        .await?;
 
     assert_not_equal(&txid, &txid_3).context("duplicate txid")?;
     assert_not_equal(&txid_2, &txid_3).context("duplicate txid")?;
@@ -783,7 +1277,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
         transfer(&p1_env, transfer_args.clone()).await?,
     )?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 5u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 5u8)
+        .await?;
 
     let now = time_nanos(&ledger_env);
 
@@ -805,7 +1301,9 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
     .await?
     .context("failed to execute the transfer with an empty subaccount")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 6u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 6u8)
+        .await?;
 
     transfer(
         &p1_env,
@@ -822,18 +1320,21 @@ pub async fn icrc1_test_tx_deduplication(ledger_env: impl LedgerEnv) -> anyhow::
     .await?
     .context("failed to execute the transfer with the default subaccount")?;
 
-    assert_balance(&p1_env, p2_env.principal(), transfer_amount.clone() * 7u8).await?;
+    balances
+        .assert(&p1_env, p2_env.principal(), transfer_amount.clone() * 7u8)
+        .await?;
 
     Ok(Outcome::Passed)
 }
 
 /// Checks the ICRC-2 transfer from endpoint for correct handling of the insufficient bad fee error.
 pub async fn icrc1_test_bad_fee(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
-    let transfer_amount = Nat::from(10_000u16);
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
     // Create two test accounts and transfer some tokens to the first account
-    let p1_env = setup_test_account(&ledger_env, initial_balance).await?;
+    let p1_env = setup_test_account(&ledger_env, initial_balance, &mut balances).await?;
     let p2_env = p1_env.fork();
 
     let mut transfer_args = Transfer::amount_to(transfer_amount.clone(), p2_env.principal());
@@ -850,7 +1351,7 @@ pub async fn icrc1_test_bad_fee(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
                     )));
                 }
             }
-            _ => return Err(anyhow::Error::msg("Expected BadFee error")),
+            _ => return Err(err).context("Expected BadFee error"),
         },
     }
     Ok(Outcome::Passed)
@@ -858,11 +1359,12 @@ pub async fn icrc1_test_bad_fee(ledger_env: impl LedgerEnv) -> anyhow::Result<Ou
 
 /// Checks the ICRC-2 transfer from endpoint for correct handling of the future transfer error.
 pub async fn icrc1_test_future_transfer(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
-    let transfer_amount = Nat::from(10_000u16);
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
     // Create two test accounts and transfer some tokens to the first account
-    let p1_env = setup_test_account(&ledger_env, initial_balance).await?;
+    let p1_env = setup_test_account(&ledger_env, initial_balance, &mut balances).await?;
     let p2_env = p1_env.fork();
 
     let mut transfer_args = Transfer::amount_to(transfer_amount, p2_env.principal());
@@ -871,85 +1373,540 @@ pub async fn icrc1_test_future_transfer(ledger_env: impl LedgerEnv) -> anyhow::R
     transfer_args = transfer_args.created_at_time(u64::MAX);
     match transfer(&ledger_env, transfer_args).await? {
         Err(TransferError::CreatedInFuture { ledger_time: _ }) => Ok(Outcome::Passed),
-        other => bail!("expected CreatedInFuture error, got: {:?}", other),
+        Ok(block) => bail!("expected CreatedInFuture error, got block {}", block),
+        Err(err) => Err(err).context("expected CreatedInFuture error"),
+    }
+}
+
+/// Checks that the ledger deduplicates a transaction until the transaction
+/// window passes, and rejects it as too old afterwards, without ever
+/// executing it twice. Moves the ledger time.
+pub async fn icrc1_test_tx_window(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let clock = match time_control(&ledger_env) {
+        Ok(clock) => clock,
+        Err(outcome) => return Ok(outcome),
+    };
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2_env = fork_account(&p1_env, &mut balances).await?;
+
+    let transfer_args = Transfer::amount_to(transfer_amount.clone(), p2_env.principal())
+        .created_at_time(time_nanos(&p1_env));
+    let txid = transfer(&p1_env, transfer_args.clone()).await??;
+
+    // The window is specific to the ledger, so move the time in growing
+    // steps until the transaction is too old.
+    let mut elapsed = Duration::ZERO;
+    let mut step = Duration::from_secs(60 * 60);
+    loop {
+        if elapsed > MAX_TX_WINDOW {
+            bail!(
+                "Expected the transaction to be too old {:?} after its creation",
+                elapsed
+            );
+        }
+        clock.advance_time(step);
+        elapsed += step;
+        step *= 2;
+
+        match transfer(&p1_env, transfer_args.clone()).await? {
+            Err(TransferError::Duplicate { duplicate_of }) => {
+                assert_equal(duplicate_of, txid.clone()).with_context(|| {
+                    format!("wrong duplicate {:?} after the transaction", elapsed)
+                })?
+            }
+            Err(TransferError::TooOld) => break,
+            other => bail!(
+                "Expected Duplicate or TooOld {:?} after the transaction, got {:?}",
+                elapsed,
+                other
+            ),
+        }
+    }
+
+    balances
+        .assert(&p2_env, p2_env.principal(), transfer_amount)
+        .await?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that the ledger accepts transactions created ahead of the ledger
+/// time only within the permitted drift. Moves the ledger time.
+///
+/// The test assumes that the permitted drift is shorter than an hour and
+/// skips the checks if the ledger accepts a transaction an hour ahead.
+pub async fn icrc1_test_created_in_future_drift(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let clock = match time_control(&ledger_env) {
+        Ok(clock) => clock,
+        Err(outcome) => return Ok(outcome),
+    };
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2_env = fork_account(&p1_env, &mut balances).await?;
+
+    let ahead = Duration::from_secs(60 * 60);
+    let now = time_nanos(&p1_env);
+    let transfer_args = Transfer::amount_to(transfer_amount.clone(), p2_env.principal())
+        .created_at_time(now + nanos(ahead));
+    match transfer(&p1_env, transfer_args.clone()).await? {
+        Err(TransferError::CreatedInFuture { ledger_time }) => {
+            if ledger_time < now || ledger_time >= now + nanos(ahead) {
+                bail!(
+                    "Expected the ledger time in CreatedInFuture to be close to {}, got {}",
+                    now,
+                    ledger_time
+                );
+            }
+        }
+        Ok(_) => {
+            return Ok(Outcome::Skipped {
+                reason: format!("the ledger permits a drift of at least {:?}", ahead),
+            });
+        }
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "Expected CreatedInFuture for a transaction {:?} ahead",
+                    ahead
+                )
+            });
+        }
     }
+    balances.assert(&p2_env, p2_env.principal(), 0u8).await?;
+
+    // Once the ledger catches up, the same transaction is within the drift.
+    clock.advance_time(ahead);
+    transfer(&p1_env, transfer_args)
+        .await?
+        .context("Expected the transaction to succeed once the ledger time reached it")?;
+    balances
+        .assert(&p2_env, p2_env.principal(), transfer_amount)
+        .await?;
+    Ok(Outcome::Passed)
 }
 
 /// Checks the ICRC-2 transfer from endpoint for correct handling of the length of the memo.
 pub async fn icrc1_test_memo_bytes_length(ledger_env: impl LedgerEnv) -> anyhow::Result<Outcome> {
+    let mut balances = Balances::default();
     let fee = transfer_fee(&ledger_env).await?;
-    let transfer_amount = Nat::from(10_000u16);
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
     let initial_balance: Nat = transfer_amount.clone() + fee.clone();
     // Create two test accounts and transfer some tokens to the first account
-    let p1_env = setup_test_account(&ledger_env, initial_balance).await?;
+    let p1_env = setup_test_account(&ledger_env, initial_balance, &mut balances).await?;
     let p2_env = p1_env.fork();
 
     let transfer_args = Transfer::amount_to(transfer_amount, p2_env.principal()).memo([1u8; 32]);
     // Ledger should accept memos of at least 32 bytes;
     match transfer(&ledger_env, transfer_args.clone()).await? {
         Ok(_) => Ok(Outcome::Passed),
-        Err(err) => bail!(
-            "Expected memo with 32 bytes to succeed but got error: {:?}",
-            err
-        ),
+        Err(err) => Err(err).context("Expected memo with 32 bytes to succeed"),
     }
 }
 
+/// An account with a subaccount of arbitrary length.
+#[derive(CandidType, Debug)]
+struct RawAccount {
+    owner: Principal,
+    subaccount: Option<Vec<u8>>,
+}
+
+/// The `icrc1_transfer` arguments without the length constraints of the
+/// typed [Transfer].
+#[derive(CandidType, Debug)]
+struct RawTransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: RawAccount,
+    amount: Nat,
+    fee: Option<Nat>,
+    created_at_time: Option<u64>,
+    memo: Option<Vec<u8>>,
+}
+
+impl RawTransferArg {
+    fn amount_to(amount: impl Into<Nat>, to: Principal) -> Self {
+        Self {
+            from_subaccount: None,
+            to: RawAccount {
+                owner: to,
+                subaccount: None,
+            },
+            amount: amount.into(),
+            fee: None,
+            created_at_time: None,
+            memo: None,
+        }
+    }
+}
+
+/// The `icrc1_transfer` arguments with a field the standard does not define.
+#[derive(CandidType, Debug)]
+struct ExtendedTransferArg {
+    to: RawAccount,
+    amount: Nat,
+    unknown_field: String,
+}
+
+/// Sends `icrc1_transfer` arguments that the ledger must refuse, and checks
+/// that the ledger traps, rejects the call, or replies with an error, and
+/// that no tokens move.
+async fn assert_transfer_refused(
+    ledger_env: &impl LedgerEnv,
+    receiver: Principal,
+    arg: Vec<u8>,
+    what: &str,
+) -> anyhow::Result<()> {
+    let sender_balance = balance_of(ledger_env, ledger_env.principal()).await?;
+    let receiver_balance = balance_of(ledger_env, receiver).await?;
+
+    match ledger_env.update_raw("icrc1_transfer", arg).await {
+        Err(LedgerCallError::Reject { .. }) => (),
+        Err(err) => return Err(err.into()),
+        Ok(reply) => {
+            let (result,): (Result<Nat, TransferError>,) =
+                decode_call_reply("icrc1_transfer", &reply)?;
+            if let Ok(block) = result {
+                bail!(
+                    "Expected a transfer with {} to fail, but it was accepted in block {}",
+                    what,
+                    block
+                );
+            }
+        }
+    }
+
+    assert_balance(ledger_env, ledger_env.principal(), sender_balance).await?;
+    assert_balance(ledger_env, receiver, receiver_balance).await
+}
+
+/// Checks that the ledger refuses transfers to and from subaccounts that are
+/// not 32 bytes long.
+pub async fn icrc1_test_malformed_subaccount(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2 = p1_env.fork().principal();
+
+    let mut arg = RawTransferArg::amount_to(transfer_amount.clone(), p2);
+    arg.to.subaccount = Some(vec![1u8; 31]);
+    let bytes = encode_args((arg,))?;
+    assert_transfer_refused(&p1_env, p2, bytes, "a 31-byte destination subaccount").await?;
+
+    let mut arg = RawTransferArg::amount_to(transfer_amount, p2);
+    arg.from_subaccount = Some(vec![0u8; 31]);
+    let bytes = encode_args((arg,))?;
+    assert_transfer_refused(&p1_env, p2, bytes, "a 31-byte source subaccount").await?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that the ledger refuses transfers with a memo longer than the
+/// maximum length it advertises.
+/// Skips the checks if the ledger does not advertise `icrc1:max_memo_length`.
+pub async fn icrc1_test_oversized_memo(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let fee = transfer_fee(&ledger_env).await?;
+    let max_memo_length = match token_metadata(&ledger_env).await?.max_memo_length {
+        Some(length) if length <= MAX_CHECKED_MEMO_LENGTH => length,
+        Some(length) => {
+            return Ok(Outcome::Skipped {
+                reason: format!(
+                    "the maximum memo length {} is too large to exceed in a transfer",
+                    length
+                ),
+            });
+        }
+        None => {
+            return Ok(Outcome::Skipped {
+                reason: "the ledger does not advertise icrc1:max_memo_length".to_string(),
+            });
+        }
+    };
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2 = p1_env.fork().principal();
+
+    let mut arg = RawTransferArg::amount_to(transfer_amount, p2);
+    arg.memo = Some(vec![1u8; max_memo_length as usize + 1]);
+    let bytes = encode_args((arg,))?;
+    let what = format!("a {}-byte memo", max_memo_length + 1);
+    assert_transfer_refused(&p1_env, p2, bytes, &what).await?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that the ledger ignores unknown fields of the transfer arguments.
+/// Candid subtyping allows callers to send record fields the receiver does
+/// not know, so the transfer must execute as if the field was absent.
+pub async fn icrc1_test_extra_record_fields(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2 = fork_account(&p1_env, &mut balances).await?.principal();
+
+    let arg = ExtendedTransferArg {
+        to: RawAccount {
+            owner: p2,
+            subaccount: None,
+        },
+        amount: transfer_amount.clone(),
+        unknown_field: "ignore me".to_string(),
+    };
+    let reply = p1_env
+        .update_raw("icrc1_transfer", encode_args((arg,))?)
+        .await?;
+    let (result,): (Result<Nat, TransferError>,) = decode_call_reply("icrc1_transfer", &reply)?;
+    result.context("Expected a transfer with an unknown field to succeed")?;
+
+    balances.assert(&p1_env, p1_env.principal(), 0u8).await?;
+    balances.assert(&p1_env, p2, transfer_amount).await?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that the ledger refuses transfers with arguments that are not
+/// valid Candid.
+pub async fn icrc1_test_invalid_candid(ledger_env: impl LedgerEnv) -> TestResult {
+    let mut balances = Balances::default();
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let p1_env =
+        setup_test_account(&ledger_env, transfer_amount.clone() + fee, &mut balances).await?;
+    let p2 = p1_env.fork().principal();
+
+    let valid = encode_args((RawTransferArg::amount_to(transfer_amount, p2),))?;
+    let truncated = valid[..valid.len() - 1].to_vec();
+    assert_transfer_refused(&p1_env, p2, truncated, "truncated arguments").await?;
+
+    let mut no_magic = valid;
+    no_magic[..4].copy_from_slice(b"LIDD");
+    assert_transfer_refused(&p1_env, p2, no_magic, "a malformed header").await?;
+
+    assert_transfer_refused(&p1_env, p2, vec![], "empty arguments").await?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that an owner can move funds between its subaccounts.
+pub async fn icrc1_test_subaccount_transfer(ledger_env: impl LedgerEnv + Clone) -> TestResult {
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let funded = transfer_amount.clone() + fee;
+    let accounts = fund_subaccounts(&ledger_env, &[("checking", funded.clone())]).await?;
+    let checking = &accounts[0];
+    let savings = checking.sibling("savings");
+    let mut balances = Balances::default();
+    balances
+        .record_funded(checking.env(), checking, funded)
+        .await?;
+    balances.record(savings.env(), &savings).await?;
+    balances
+        .record(checking.env(), checking.env().principal())
+        .await?;
+
+    checking
+        .transfer(transfer_amount.clone(), &savings)
+        .await?
+        .context("failed to transfer between subaccounts of the same owner")?;
+
+    balances.assert(checking.env(), checking, 0u8).await?;
+    balances
+        .assert(savings.env(), &savings, transfer_amount)
+        .await?;
+    balances
+        .assert(checking.env(), checking.env().principal(), 0u8)
+        .await
+        .context("the transfer changed the default account of the owner")?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that transfers with `from_subaccount` debit the subaccount.
+pub async fn icrc1_test_from_subaccount(ledger_env: impl LedgerEnv + Clone) -> TestResult {
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let remainder = Nat::from(5_000u16);
+    let funded = transfer_amount.clone() + fee + remainder.clone();
+    let accounts = fund_subaccounts(&ledger_env, &[("savings", funded.clone())]).await?;
+    let savings = &accounts[0];
+    let mut balances = Balances::default();
+    balances
+        .record_funded(savings.env(), savings, funded)
+        .await?;
+    balances
+        .record(savings.env(), savings.env().principal())
+        .await?;
+    let receiver = fork_account(&ledger_env, &mut balances).await?.principal();
+
+    savings
+        .transfer(transfer_amount.clone(), receiver)
+        .await?
+        .context("failed to transfer from a subaccount")?;
+
+    balances
+        .assert(&ledger_env, receiver, transfer_amount)
+        .await?;
+    balances.assert(savings.env(), savings, remainder).await?;
+    balances
+        .assert(savings.env(), savings.env().principal(), 0u8)
+        .await
+        .context("the transfer changed the default account of the owner")?;
+    Ok(Outcome::Passed)
+}
+
+/// Checks that an account can only spend its own balance, even if other
+/// subaccounts of the same owner hold enough funds.
+pub async fn icrc1_test_subaccount_isolation(ledger_env: impl LedgerEnv + Clone) -> TestResult {
+    let fee = transfer_fee(&ledger_env).await?;
+    let transfer_amount = test_amount(&ledger_env).await?.into_base_units();
+    let initial_balance = transfer_amount.clone() + fee;
+    let accounts = fund_subaccounts(
+        &ledger_env,
+        &[
+            ("a", initial_balance.clone()),
+            ("b", initial_balance.clone()),
+        ],
+    )
+    .await?;
+    let (a, b) = (&accounts[0], &accounts[1]);
+    let mut balances = Balances::default();
+    balances
+        .record_funded(a.env(), a, initial_balance.clone())
+        .await?;
+    balances
+        .record_funded(b.env(), b, initial_balance.clone())
+        .await?;
+    balances.record(a.env(), a.env().principal()).await?;
+    let receiver = fork_account(&ledger_env, &mut balances).await?.principal();
+
+    // The owner holds enough funds in total, but not in subaccount a.
+    assert_equal(
+        a.transfer(transfer_amount.clone() + transfer_amount.clone(), receiver)
+            .await?,
+        Err(TransferError::InsufficientFunds {
+            balance: balances.expected(&a.account(), initial_balance.clone())?,
+        }),
+    )
+    .context("subaccount a spent the funds of another subaccount")?;
+
+    assert_equal(
+        transfer(
+            a.env(),
+            Transfer::amount_to(transfer_amount.clone(), receiver),
+        )
+        .await?,
+        Err(TransferError::InsufficientFunds {
+            balance: balances.expected(&a.env().principal().into(), 0u8)?,
+        }),
+    )
+    .context("the default account spent the funds of a subaccount")?;
+
+    a.transfer(transfer_amount, receiver)
+        .await?
+        .context("failed to transfer from subaccount a")?;
+
+    balances.assert(a.env(), a, 0u8).await?;
+    balances
+        .assert(b.env(), b, initial_balance)
+        .await
+        .context("a transfer from subaccount a changed the balance of subaccount b")?;
+    Ok(Outcome::Passed)
+}
+
 /// Returns the entire list of icrc1 tests.
 pub fn icrc1_test_suite(env: impl LedgerEnv + 'static + Clone) -> Vec<Test> {
     vec![
-        test("icrc1:transfer", icrc1_test_transfer(env.clone())),
-        test("icrc1:burn", icrc1_test_burn(env.clone())),
-        test("icrc1:metadata", icrc1_test_metadata(env.clone())),
-        test(
+        ledger_test(&env, "icrc1:transfer", icrc1_test_transfer),
+        ledger_test(&env, "icrc1:burn", icrc1_test_burn),
+        ledger_test(&env, "icrc1:metadata", icrc1_test_metadata),
+        ledger_test(
+            &env,
             "icrc1:supported_standards",
-            icrc1_test_supported_standards(env.clone()),
-        ),
-        test(
-            "icrc1:tx_deduplication",
-            icrc1_test_tx_deduplication(env.clone()),
+            icrc1_test_supported_standards,
         ),
-        test(
+        ledger_test(&env, "icrc1:candid_interface", icrc1_test_candid_interface),
+        ledger_test(&env, "icrc1:tx_deduplication", icrc1_test_tx_deduplication),
+        ledger_test(
+            &env,
             "icrc1:memo_bytes_length",
-            icrc1_test_memo_bytes_length(env.clone()),
+            icrc1_test_memo_bytes_length,
+        ),
+        ledger_test(&env, "icrc1:future_transfers", icrc1_test_future_transfer),
+        ledger_test(&env, "icrc1:bad_fee", icrc1_test_bad_fee),
+        ledger_test(
+            &env,
+            "icrc1:malformed_subaccount",
+            icrc1_test_malformed_subaccount,
+        ),
+        ledger_test(&env, "icrc1:oversized_memo", icrc1_test_oversized_memo),
+        ledger_test(
+            &env,
+            "icrc1:extra_record_fields",
+            icrc1_test_extra_record_fields,
+        ),
+        ledger_test(&env, "icrc1:invalid_candid", icrc1_test_invalid_candid),
+        ledger_test(
+            &env,
+            "icrc1:subaccount_transfer",
+            icrc1_test_subaccount_transfer,
         ),
-        test(
-            "icrc1:future_transfers",
-            icrc1_test_future_transfer(env.clone()),
+        ledger_test(&env, "icrc1:from_subaccount", icrc1_test_from_subaccount),
+        ledger_test(
+            &env,
+            "icrc1:subaccount_isolation",
+            icrc1_test_subaccount_isolation,
+        ),
+        exclusive_ledger_test(&env, "icrc1:supply", icrc1_test_supply),
+        exclusive_ledger_test(&env, "icrc1:tx_window", icrc1_test_tx_window),
+        exclusive_ledger_test(
+            &env,
+            "icrc1:created_in_future_drift",
+            icrc1_test_created_in_future_drift,
         ),
-        test("icrc1:bad_fee", icrc1_test_bad_fee(env)),
     ]
 }
 
 /// Returns the entire list of icrc2 tests.
 pub fn icrc2_test_suite(env: impl LedgerEnv + 'static + Clone) -> Vec<Test> {
     vec![
-        test(
+        ledger_test(
+            &env,
             "icrc2:supported_standards",
-            icrc2_test_supported_standards(env.clone()),
+            icrc2_test_supported_standards,
         ),
-        test("icrc2:approve", icrc2_test_approve(env.clone())),
-        test(
+        ledger_test(&env, "icrc2:approve", icrc2_test_approve),
+        ledger_test(
+            &env,
             "icrc2:approve_expiration",
-            icrc2_test_approve_expiration(env.clone()),
+            icrc2_test_approve_expiration,
         ),
-        test(
+        ledger_test(
+            &env,
             "icrc2:approve_expected_allowance",
-            icrc2_test_approve_expected_allowance(env.clone()),
+            icrc2_test_approve_expected_allowance,
         ),
-        test("icrc2:transfer_from", icrc2_test_transfer_from(env.clone())),
-        test(
+        ledger_test(&env, "icrc2:transfer_from", icrc2_test_transfer_from),
+        ledger_test(
+            &env,
             "icrc2:transfer_from_insufficient_funds",
-            icrc2_test_transfer_from_insufficient_funds(env.clone()),
+            icrc2_test_transfer_from_insufficient_funds,
         ),
-        test(
+        ledger_test(
+            &env,
             "icrc2:transfer_from_insufficient_allowance",
-            icrc2_test_transfer_from_insufficient_allowance(env.clone()),
+            icrc2_test_transfer_from_insufficient_allowance,
         ),
-        test(
+        ledger_test(
+            &env,
             "icrc2:transfer_from_self",
-            icrc2_test_transfer_from_self(env.clone()),
+            icrc2_test_transfer_from_self,
         ),
+        exclusive_ledger_test(&env, "icrc2:approval_expiry", icrc2_test_approval_expiry),
     ]
 }
 
@@ -971,44 +1928,147 @@ pub async fn test_suite(env: impl LedgerEnv + 'static + Clone) -> Vec<Test> {
         }
     }
 }
+
+/// Returns whether a test failed because of the infrastructure rather than
+/// the ledger: a fault injected by `FaultyLedger`, a transient reject, or
+/// the ledger replying that it is temporarily unavailable. `FaultyLedger`
+/// injects the latter two as well.
+fn is_infrastructure_fault(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause.is::<InjectedFault>()
+            || matches!(
+                cause.downcast_ref(),
+                Some(LedgerCallError::Reject {
+                    code: RejectCode::SysTransient,
+                    ..
+                })
+            )
+            || matches!(
+                cause.downcast_ref(),
+                Some(TransferError::TemporarilyUnavailable)
+            )
+            || matches!(
+                cause.downcast_ref(),
+                Some(ApproveError::TemporarilyUnavailable)
+            )
+            || matches!(
+                cause.downcast_ref(),
+                Some(TransferFromError::TemporarilyUnavailable)
+            )
+    })
+}
+
+/// Prints the result of a test using the TAP protocol and returns true if
+/// the test did not fail.
+fn report_result(number: usize, name: &str, result: TestResult) -> bool {
+    match result {
+        Ok(Outcome::Passed) => {
+            println!("ok {} - {}", number, name);
+            true
+        }
+        Ok(Outcome::Skipped { reason }) => {
+            println!("ok {} - {} # SKIP {}", number, name, reason);
+            true
+        }
+        Err(err) => {
+            for line in format!("{:?}", err).lines() {
+                println!("# {}", line);
+            }
+
+            if is_infrastructure_fault(&err) {
+                println!("not ok {} - {} # infrastructure fault", number, name);
+            } else {
+                println!("not ok {} - {}", number, name);
+            }
+            false
+        }
+    }
+}
+
 /// Executes the list of tests concurrently and prints results using
 /// the TAP protocol (https://testanything.org/).
+///
+/// Exclusive tests run one at a time after all other tests completed.
 pub async fn execute_tests(tests: Vec<Test>) -> bool {
     use futures::stream::FuturesOrdered;
 
+    let (exclusive, concurrent): (Vec<_>, Vec<_>) =
+        tests.into_iter().partition(|test| test.exclusive);
+
     let mut names = Vec::new();
     let mut futures = FuturesOrdered::new();
 
-    for test in tests.into_iter() {
+    for test in concurrent.into_iter() {
         names.push(test.name);
         futures.push_back(test.action);
     }
 
     println!("TAP version 14");
-    println!("1..{}", futures.len());
+    println!("1..{}", futures.len() + exclusive.len());
 
     let mut idx = 0;
     let mut success = true;
     while let Some(result) = futures.next().await {
-        match result {
-            Ok(Outcome::Passed) => {
-                println!("ok {} - {}", idx + 1, names[idx]);
-            }
-            Ok(Outcome::Skipped { reason }) => {
-                println!("ok {} - {} # SKIP {}", idx + 1, names[idx], reason);
-            }
-            Err(err) => {
-                success = false;
-
-                for line in format!("{:?}", err).lines() {
-                    println!("# {}", line);
-                }
+        success &= report_result(idx + 1, &names[idx], result);
+        idx += 1;
+    }
 
-                println!("not ok {} - {}", idx + 1, names[idx]);
-            }
-        }
+    for test in exclusive.into_iter() {
+        let result = test.action.await;
+        success &= report_result(idx + 1, &test.name, result);
         idx += 1;
     }
 
     success
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temporarily_unavailable_is_infrastructure_fault() {
+        let unavailable = anyhow::Error::new(TransferFromError::TemporarilyUnavailable)
+            .context("expected TransferFromError::InsufficientFunds");
+        assert!(is_infrastructure_fault(&unavailable));
+        assert!(is_infrastructure_fault(&anyhow::Error::new(
+            ApproveError::TemporarilyUnavailable
+        )));
+        let bad_fee = anyhow::Error::new(TransferError::BadFee {
+            expected_fee: Nat::from(10u8),
+        })
+        .context("expected TransferError::InsufficientFunds");
+        assert!(!is_infrastructure_fault(&bad_fee));
+    }
+
+    #[test]
+    fn test_transient_reject_is_infrastructure_fault() {
+        let reject = |code| LedgerCallError::Reject {
+            method: "icrc1_transfer".to_string(),
+            code,
+            message: "rejected".to_string(),
+        };
+        assert!(is_infrastructure_fault(
+            &anyhow::Error::new(reject(RejectCode::SysTransient)).context("transfer failed")
+        ));
+        assert!(!is_infrastructure_fault(&anyhow::Error::new(reject(
+            RejectCode::CanisterError
+        ))));
+    }
+
+    #[test]
+    fn test_standard_interfaces_match_standards() {
+        let standards = [
+            include_str!("../../standards/ICRC-1/ICRC-1.did"),
+            include_str!("../../standards/ICRC-2/ICRC-2.did"),
+            include_str!("../../standards/ICRC-3/ICRC-3.did"),
+        ];
+        for ((name, copy), standard) in STANDARD_INTERFACES.iter().zip(standards) {
+            assert_eq!(
+                copy, &standard,
+                "test/suite/standards/{}.did differs from the standard",
+                name
+            );
+        }
+    }
+}